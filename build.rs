@@ -0,0 +1,40 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Docker builds don't see .git, so allow passing the hash in from outside.
+    let git_hash = std::env::var("GIT_HASH")
+        .ok()
+        .filter(|hash| !hash.is_empty())
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "--short", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .map(|hash| hash.trim().to_owned())
+        })
+        .unwrap_or_else(|| "unknown".to_owned());
+    let build_timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    println!("cargo:rustc-env=GIT_HASH={git_hash}");
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={build_timestamp}");
+    // HEAD only changes on a checkout. A commit moves the branch it points at, which lives
+    // in its own file until `git gc` packs it. Missing files would make every build rerun
+    // this script, so only the ones that exist are watched.
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    let branch = std::fs::read_to_string(".git/HEAD")
+        .ok()
+        .and_then(|head| head.trim().strip_prefix("ref: ").map(|branch| format!(".git/{branch}")));
+    for path in branch.iter().map(String::as_str).chain([".git/packed-refs"]) {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+    println!("cargo:rerun-if-env-changed=GIT_HASH");
+}
@@ -0,0 +1,60 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::web::Data;
+use actix_web::Error;
+use futures::FutureExt;
+use tracing::info;
+
+use crate::client_ip;
+use crate::settings::Settings;
+
+/// Attached to the response extensions by handlers that render or hit the cache.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderOutcome {
+    pub cache_hit: bool,
+    pub render_time: Option<Duration>,
+}
+
+// One line per request with fixed field names, independent of the OTLP exporters. It has
+// to sit innermost so the body size is the one the handler produced.
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let started = Instant::now();
+    let method = req.method().clone();
+    let path = req.path().to_owned();
+    let client_ip = req.app_data::<Data<Settings>>().and_then(|settings| {
+        client_ip::client_ip(req.request(), &settings.trusted_proxies, settings.forwarded_header)
+    });
+    srv.call(req).map(move |result| {
+        if let Ok(res) = &result {
+            let bytes = match res.response().body().size() {
+                BodySize::Sized(bytes) => Some(bytes),
+                _ => None,
+            };
+            let outcome = res.response().extensions().get::<RenderOutcome>().copied();
+            info!(
+                method = %method,
+                path = %path,
+                client_ip = client_ip.map(tracing::field::display),
+                status = res.status().as_u16(),
+                bytes,
+                cache_hit = outcome.map(|outcome| outcome.cache_hit),
+                render_ms = outcome
+                    .and_then(|outcome| outcome.render_time)
+                    .map(|render_time| render_time.as_millis() as u64),
+                duration_ms = started.elapsed().as_millis() as u64,
+                "request"
+            );
+        }
+        result
+    })
+}
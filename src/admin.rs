@@ -0,0 +1,40 @@
+use actix_web::{post, HttpRequest, HttpResponse, web::Data};
+use serde::Serialize;
+use subtle::ConstantTimeEq;
+use tracing::info;
+
+use crate::error::ApiError;
+use crate::service;
+use crate::settings::Settings;
+
+const ADMIN_TOKEN_HEADER: &str = "X-Admin-Token";
+
+#[derive(Debug, Serialize)]
+struct PurgeResponse {
+    removed: usize,
+}
+
+#[post("/admin/cache/purge")]
+pub async fn purge_cache(
+    req: HttpRequest,
+    db: Data<sled::Db>,
+    settings: Data<Settings>,
+) -> Result<HttpResponse, ApiError> {
+    let token = req
+        .headers()
+        .get(ADMIN_TOKEN_HEADER)
+        .and_then(|token| token.to_str().ok());
+    // Compared in constant time so the response time doesn't reveal matching prefixes.
+    let authorized = match (settings.admin_token.as_deref(), token) {
+        (Some(expected), Some(token)) => bool::from(expected.as_bytes().ct_eq(token.as_bytes())),
+        _ => false,
+    };
+    if !authorized {
+        return Err(ApiError::Unauthorized);
+    }
+
+    let removed = service::purge(&db)?;
+    info!("Purged {removed} cached images");
+
+    Ok(HttpResponse::Ok().json(PurgeResponse { removed }))
+}
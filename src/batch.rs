@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+use std::io::{Cursor, Write};
+
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{get, web::{self, Data}, HttpResponse};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use zip::result::ZipError;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::dimensions::DimensionError;
+use crate::error::ApiError;
+use crate::settings::Settings;
+
+const MAX_SPECS: usize = 64;
+
+#[derive(Debug, Deserialize)]
+pub struct BatchQuery {
+    specs: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ManifestEntry {
+    spec: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// Specs are comma separated `dimensions?query` like PLATZHALTER_WARMUP, so the whole list
+// has to be percent-encoded as a single value. A spec that fails only shows up with its
+// error in manifest.json instead of failing the whole archive.
+#[get("/batch")]
+pub async fn batch(
+    web::Query(query): web::Query<BatchQuery>,
+    db: Data<sled::Db>,
+    settings: Data<Settings>,
+    render_permits: Data<Semaphore>,
+) -> Result<HttpResponse, ApiError> {
+    let specs: Vec<&str> = query
+        .specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_SPECS {
+        return Err(ApiError::InvalidParameter(format!(
+            "a batch needs between 1 and {MAX_SPECS} specs"
+        )));
+    }
+
+    // Everything is parsed up front so the pixel budget is checked before any rendering.
+    let metas: Vec<_> = specs
+        .iter()
+        .map(|spec| crate::parse_spec(spec, &settings))
+        .collect();
+    let pixels: u64 = metas
+        .iter()
+        .flatten()
+        .map(|meta| meta.length as u64 * meta.height as u64)
+        .sum();
+    if pixels > settings.max_pixels {
+        return Err(DimensionError::TooManyPixels(settings.max_pixels).into());
+    }
+
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    // Images are already compressed, deflating them again only costs time.
+    let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut names = HashSet::new();
+    let mut manifest = Vec::with_capacity(specs.len());
+    for (spec, meta) in specs.into_iter().zip(metas) {
+        let rendered = match meta {
+            Ok(meta) => {
+                let (length, height) = (meta.length, meta.height);
+                let format = meta.config.fmt.unwrap_or_default();
+                crate::cached_render(meta, length, height, false, &db, &settings, &render_permits)
+                    .await
+                    .map(|image| (image.bytes, format, length, height))
+            }
+            Err(err) => Err(err),
+        };
+        let entry = match rendered {
+            Ok((bytes, format, length, height)) => {
+                let file = unique_name(&mut names, length, height, format.extension());
+                archive.start_file(file.as_str(), options)?;
+                archive.write_all(&bytes).map_err(ZipError::from)?;
+                ManifestEntry {
+                    spec: spec.to_owned(),
+                    file: Some(file),
+                    error: None,
+                }
+            }
+            Err(err) => ManifestEntry {
+                spec: spec.to_owned(),
+                file: None,
+                error: Some(err.to_string()),
+            },
+        };
+        manifest.push(entry);
+    }
+
+    archive.start_file("manifest.json", options)?;
+    let manifest = serde_json::to_vec_pretty(&manifest).expect("manifest is always serializable");
+    archive.write_all(&manifest).map_err(ZipError::from)?;
+    let archive = archive.finish()?.into_inner();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("placeholders.zip".to_owned())],
+        })
+        .body(archive))
+}
+
+// The same size and format can be requested with different options, so later files get a
+// counter appended instead of overwriting earlier ones.
+fn unique_name(names: &mut HashSet<String>, length: i32, height: i32, extension: &str) -> String {
+    let mut name = format!("{length}x{height}.{extension}");
+    let mut counter = 2;
+    while !names.insert(name.clone()) {
+        name = format!("{length}x{height}-{counter}.{extension}");
+        counter += 1;
+    }
+    name
+}
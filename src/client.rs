@@ -0,0 +1,179 @@
+//! Builds placeholder URLs without assembling query strings by hand.
+
+use crate::color::Color;
+use crate::format::OutputFormat;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Label {
+    Size,
+    Wxh,
+    None,
+}
+
+impl Label {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Label::Size => "size",
+            Label::Wxh => "wxh",
+            Label::None => "none",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaceholderRequest {
+    width: u32,
+    height: u32,
+    bg: Option<Color>,
+    tc: Option<Color>,
+    format: Option<OutputFormat>,
+    quality: Option<u8>,
+    label: Option<Label>,
+    font: Option<String>,
+}
+
+impl PlaceholderRequest {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            bg: None,
+            tc: None,
+            format: None,
+            quality: None,
+            label: None,
+            font: None,
+        }
+    }
+
+    pub fn bg(mut self, color: Color) -> Self {
+        self.bg = Some(color);
+        self
+    }
+
+    pub fn text_color(mut self, color: Color) -> Self {
+        self.tc = Some(color);
+        self
+    }
+
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn quality(mut self, quality: u8) -> Self {
+        self.quality = Some(quality);
+        self
+    }
+
+    pub fn label(mut self, label: Label) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    pub fn font(mut self, family: &str) -> Self {
+        self.font = Some(family.to_owned());
+        self
+    }
+
+    /// Unset options are left out, so the server falls back to its own defaults for them.
+    pub fn to_url(&self, base: &str) -> String {
+        let mut query = Vec::new();
+        if let Some(bg) = &self.bg {
+            query.push(("bg", color_param(bg)));
+        }
+        if let Some(tc) = &self.tc {
+            query.push(("tc", color_param(tc)));
+        }
+        if let Some(format) = &self.format {
+            query.push(("fmt", format.extension().to_owned()));
+        }
+        if let Some(quality) = self.quality {
+            query.push(("q", quality.to_string()));
+        }
+        if let Some(label) = &self.label {
+            query.push(("label", label.as_str().to_owned()));
+        }
+        if let Some(font) = &self.font {
+            query.push(("font", font.clone()));
+        }
+
+        let mut url = format!(
+            "{}/{}x{}",
+            base.trim_end_matches('/'),
+            self.width,
+            self.height
+        );
+        for (index, (name, value)) in query.iter().enumerate() {
+            url.push(if index == 0 { '?' } else { '&' });
+            url.push_str(name);
+            url.push('=');
+            url.push_str(&encode_query_value(value));
+        }
+        url
+    }
+}
+
+// Keeps the characters rgba() needs readable and percent-encodes everything that could
+// end the value or the query early.
+fn encode_query_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'(' | b')'
+            | b',' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+// `to_hex` drops alpha, so translucent colors go through rgba() instead.
+fn color_param(color: &Color) -> String {
+    if color.a == 255 {
+        color.to_hex()
+    } else {
+        format!(
+            "rgba({},{},{},{})",
+            color.r,
+            color.g,
+            color.b,
+            color.a as f64 / 255.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_out_unset_options() {
+        let url = PlaceholderRequest::new(800, 450).to_url("https://example.com/");
+        assert_eq!(url, "https://example.com/800x450");
+    }
+
+    #[test]
+    fn builds_the_full_query() {
+        let url = PlaceholderRequest::new(300, 200)
+            .bg(Color::rgb(0xff, 0xd8, 0xc2))
+            .text_color(Color::rgba(0, 0, 0, 128))
+            .format(OutputFormat::Webp)
+            .quality(80)
+            .label(Label::Wxh)
+            .to_url("http://localhost:8000");
+        assert_eq!(
+            url,
+            "http://localhost:8000/300x200?bg=ffd8c2&tc=rgba(0,0,0,0.5019607843137255)\
+             &fmt=webp&q=80&label=wxh"
+        );
+    }
+
+    #[test]
+    fn percent_encodes_text() {
+        let url = PlaceholderRequest::new(10, 10)
+            .font("Noto Sans&q=1/ü")
+            .to_url("http://localhost");
+        assert_eq!(url, "http://localhost/10x10?font=Noto%20Sans%26q%3D1%2F%C3%BC");
+    }
+}
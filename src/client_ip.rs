@@ -0,0 +1,149 @@
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::HttpRequest;
+use ipnet::IpNet;
+
+/// The header the trusted proxies record the client address in. Only that one is read,
+/// because a proxy leaves the other one untouched and the client could have sent it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ForwardedHeader {
+    #[default]
+    XForwardedFor,
+    Forwarded,
+}
+
+// Proxies append to the forwarding header, so the chain is walked from the peer
+// backwards and the first address not belonging to a trusted proxy is the client.
+// Anything further left could have been sent by the client itself.
+pub fn client_ip(
+    req: &HttpRequest,
+    trusted_proxies: &[IpNet],
+    forwarded_header: ForwardedHeader,
+) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer) {
+        return Some(peer);
+    }
+
+    let forwarded = forwarded_for(req.headers(), forwarded_header);
+    let client = forwarded
+        .iter()
+        .rev()
+        .find(|ip| !is_trusted(ip))
+        .or_else(|| forwarded.first())
+        .copied()
+        .unwrap_or(peer);
+    Some(client)
+}
+
+fn forwarded_for(headers: &HeaderMap, forwarded_header: ForwardedHeader) -> Vec<IpAddr> {
+    match forwarded_header {
+        ForwardedHeader::Forwarded => headers
+            .get_all(header::FORWARDED)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node))
+            })
+            .collect(),
+        ForwardedHeader::XForwardedFor => headers
+            .get_all(header::X_FORWARDED_FOR)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(parse_node)
+            .collect(),
+    }
+}
+
+// Nodes look like `192.0.2.60`, `"192.0.2.60:4711"` or `"[2001:db8::1]:4711"`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            node.strip_prefix('[')
+                .and_then(|node| node.strip_suffix(']'))
+                .and_then(|node| node.parse().ok())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    const PROXY: &str = "10.0.0.1:443";
+
+    fn trusted() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap()]
+    }
+
+    fn resolve(peer: &str, header: (&str, &str), forwarded_header: ForwardedHeader) -> IpAddr {
+        let req = TestRequest::default()
+            .peer_addr(peer.parse().unwrap())
+            .insert_header(header)
+            .to_http_request();
+        client_ip(&req, &trusted(), forwarded_header).unwrap()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer_is_the_client() {
+        let header = ("X-Forwarded-For", "192.0.2.60");
+        let client = resolve("198.51.100.7:5000", header, ForwardedHeader::XForwardedFor);
+        assert_eq!(client, ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn walks_the_chain_from_the_right() {
+        // The client made up the first entry, the trusted proxy appended the real one.
+        let header = ("X-Forwarded-For", "203.0.113.9, 192.0.2.60, 10.1.2.3");
+        let client = resolve(PROXY, header, ForwardedHeader::XForwardedFor);
+        assert_eq!(client, ip("192.0.2.60"));
+    }
+
+    #[test]
+    fn all_trusted_chain_falls_back_to_the_leftmost_entry() {
+        let header = ("X-Forwarded-For", "10.9.9.9, 10.1.2.3");
+        let client = resolve(PROXY, header, ForwardedHeader::XForwardedFor);
+        assert_eq!(client, ip("10.9.9.9"));
+        let client = resolve(PROXY, ("X-Forwarded-For", ""), ForwardedHeader::XForwardedFor);
+        assert_eq!(client, ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn parses_quoted_ports_and_ipv6_brackets() {
+        let header = ("Forwarded", r#"for="192.0.2.60:4711";proto=https"#);
+        assert_eq!(resolve(PROXY, header, ForwardedHeader::Forwarded), ip("192.0.2.60"));
+        let header = ("Forwarded", r#"for="[2001:db8::1]:4711", for=10.1.2.3"#);
+        assert_eq!(resolve(PROXY, header, ForwardedHeader::Forwarded), ip("2001:db8::1"));
+        let header = ("Forwarded", r#"For="[2001:db8::2]""#);
+        assert_eq!(resolve(PROXY, header, ForwardedHeader::Forwarded), ip("2001:db8::2"));
+        let header = ("X-Forwarded-For", "2001:db8::3");
+        assert_eq!(resolve(PROXY, header, ForwardedHeader::XForwardedFor), ip("2001:db8::3"));
+    }
+
+    #[test]
+    fn ignores_the_header_that_is_not_configured() {
+        let req = TestRequest::default()
+            .peer_addr(PROXY.parse().unwrap())
+            .insert_header(("Forwarded", "for=203.0.113.9"))
+            .insert_header(("X-Forwarded-For", "192.0.2.60"))
+            .to_http_request();
+        let client = client_ip(&req, &trusted(), ForwardedHeader::XForwardedFor);
+        assert_eq!(client, Some(ip("192.0.2.60")));
+        let client = client_ip(&req, &trusted(), ForwardedHeader::Forwarded);
+        assert_eq!(client, Some(ip("203.0.113.9")));
+    }
+}
@@ -3,12 +3,22 @@ use std::hash::Hash;
 use serde::Deserialize;
 use thiserror::Error;
 
+pub const DEFAULT_LUMA_THRESHOLD: f64 = 80.0;
+
 #[derive(Debug, Error)]
 pub enum ColorError {
     #[error("only hex strings of length 3 or 6 are supported")]
     InvalidLength,
     #[error("values of the triplet must be valid hex")]
     InvalidHex(#[from] std::num::ParseIntError),
+    #[error("only rgb(r, g, b) and rgba(r, g, b, a) functions are supported")]
+    InvalidFunction,
+    #[error("color channel `{0}` must be an integer between 0 and 255")]
+    InvalidChannel(String),
+    #[error("alpha `{0}` must be a number between 0 and 1")]
+    InvalidAlpha(String),
+    #[error("`{0}` is not a hex color, rgb() or rgba()")]
+    Unrecognized(String),
 }
 
 #[derive(Debug)]
@@ -19,21 +29,48 @@ pub struct ScaledColor {
     pub a: f64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PerceivedLuminance {
     Light,
     Dark,
 }
 
-#[derive(Debug, Hash, Deserialize, Default)]
+/// Alpha is always 0 to 255 and every way of building a color defaults it to opaque, so
+/// the same visible color can't end up with different cache keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
     pub b: u8,
+    #[serde(default = "opaque")]
     pub a: u8,
 }
 
+impl Default for Color {
+    fn default() -> Self {
+        Self::rgb(0, 0, 0)
+    }
+}
+
+fn opaque() -> u8 {
+    255
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Background {
+    Color(Color),
+    Random,
+}
+
 impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self::rgba(r, g, b, 255)
+    }
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
     pub fn from_hex(hex: &str) -> Result<Self, ColorError> {
         if hex.len() != 3 && hex.len() != 6 {
             return Err(ColorError::InvalidLength);
@@ -52,34 +89,125 @@ impl Color {
             r: red,
             g: green,
             b: blue,
-            a: 1u8,
+            a: 255u8,
         })
     }
 
-    pub fn perceived_luminance(&self) -> PerceivedLuminance {
-        let ScaledColor { r, g, b, .. } = self.to_scaled();
-        let r = srgb_to_linear(r);
-        let g = srgb_to_linear(g);
-        let b = srgb_to_linear(b);
-        let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
-        let perceived_lum = luminance_to_perceived_luminance(luminance);
-        if perceived_lum >= 80.0 {
+    pub fn from_function(function: &str) -> Result<Self, ColorError> {
+        let (name, args) = function
+            .strip_suffix(')')
+            .and_then(|function| function.split_once('('))
+            .ok_or(ColorError::InvalidFunction)?;
+        let args: Vec<&str> = args.split(',').map(str::trim).collect();
+        match (name, args.as_slice()) {
+            ("rgb", [r, g, b]) => Ok(Self {
+                r: parse_channel(r)?,
+                g: parse_channel(g)?,
+                b: parse_channel(b)?,
+                a: 255u8,
+            }),
+            ("rgba", [r, g, b, a]) => Ok(Self {
+                r: parse_channel(r)?,
+                g: parse_channel(g)?,
+                b: parse_channel(b)?,
+                a: parse_alpha(a)?,
+            }),
+            _ => Err(ColorError::InvalidFunction),
+        }
+    }
+
+    pub fn from_hsl(hue: f64, saturation: f64, lightness: f64) -> Self {
+        let hue = hue.rem_euclid(360.0);
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        let x = chroma * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+        let m = lightness - chroma / 2.0;
+        let (r, g, b) = match hue as u32 / 60 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let to_channel = |channel: f64| ((channel + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+
+        Self {
+            r: to_channel(r),
+            g: to_channel(g),
+            b: to_channel(b),
+            a: 255u8,
+        }
+    }
+
+    /// Source-over compositing of this color onto `background`.
+    pub fn over(&self, background: &Color) -> Color {
+        let top = self.to_scaled();
+        let bottom = background.to_scaled();
+        let alpha = top.a + bottom.a * (1.0 - top.a);
+        if alpha == 0.0 {
+            return Color::rgba(0, 0, 0, 0);
+        }
+        let channel = |top_channel: f64, bottom_channel: f64| {
+            let value = (top_channel * top.a + bottom_channel * bottom.a * (1.0 - top.a)) / alpha;
+            (value * 255.0).round().clamp(0.0, 255.0) as u8
+        };
+        Color::rgba(
+            channel(top.r, bottom.r),
+            channel(top.g, bottom.g),
+            channel(top.b, bottom.b),
+            (alpha * 255.0).round() as u8,
+        )
+    }
+
+    pub fn perceived_luminance(&self, threshold: f64) -> PerceivedLuminance {
+        let perceived_lum = luminance_to_perceived_luminance(self.relative_luminance());
+        if perceived_lum >= threshold {
             PerceivedLuminance::Light
         } else {
             PerceivedLuminance::Dark
         }
     }
 
+    /// WCAG contrast ratio between 1 and 21, ignoring alpha.
+    pub fn contrast_ratio(&self, other: &Color) -> f64 {
+        let (a, b) = (self.relative_luminance(), other.relative_luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    fn relative_luminance(&self) -> f64 {
+        let ScaledColor { r, g, b, .. } = self.to_scaled();
+        0.2126 * srgb_to_linear(r) + 0.7152 * srgb_to_linear(g) + 0.0722 * srgb_to_linear(b)
+    }
+
+    /// Lowercase `rrggbb` without a leading `#`, the format `from_hex` reads. Alpha is
+    /// not part of it.
+    pub fn to_hex(&self) -> String {
+        format!("{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
     pub fn to_scaled(&self) -> ScaledColor {
         ScaledColor {
             r: self.r as f64 / 255.0,
             g: self.g as f64 / 255.0,
             b: self.b as f64 / 255.0,
-            a: self.a as f64,
+            a: self.a as f64 / 255.0,
         }
     }
 }
 
+fn parse_channel(channel: &str) -> Result<u8, ColorError> {
+    channel
+        .parse()
+        .map_err(|_| ColorError::InvalidChannel(channel.to_owned()))
+}
+
+fn parse_alpha(alpha: &str) -> Result<u8, ColorError> {
+    match alpha.parse::<f64>() {
+        Ok(value) if (0.0..=1.0).contains(&value) => Ok((value * 255.0).round() as u8),
+        _ => Err(ColorError::InvalidAlpha(alpha.to_owned())),
+    }
+}
+
 fn srgb_to_linear(channel: f64) -> f64 {
     if channel <= 0.04045 {
         channel / 12.92
@@ -95,3 +223,60 @@ fn luminance_to_perceived_luminance(luminance: f64) -> f64 {
         f64::powf(luminance, 1.0 / 3.0) * 116.0 - 16.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_light(hex: &str, threshold: f64) -> bool {
+        let color = Color::from_hex(hex).unwrap();
+        matches!(color.perceived_luminance(threshold), PerceivedLuminance::Light)
+    }
+
+    #[test]
+    fn threshold_boundary_counts_as_light() {
+        assert!(is_light("FFFFFF", 100.0));
+        assert!(is_light("000000", 0.0));
+        assert!(!is_light("000000", 0.1));
+
+        // #777777 is just above 50 on the perceived lightness scale.
+        assert!(is_light("777777", 50.0));
+        assert!(!is_light("777777", 50.1));
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        for hex in ["000000", "ffffff", "ffd8c2", "0a1b2c"] {
+            assert_eq!(Color::from_hex(hex).unwrap().to_hex(), hex);
+        }
+        assert_eq!(Color::from_hex("F0A").unwrap().to_hex(), "ff00aa");
+        assert_eq!(Color::from_hex("FFD8C2").unwrap(), Color::rgb(0xff, 0xd8, 0xc2));
+        assert!(Color::from_hex("ffd8c").is_err());
+        assert!(Color::from_hex("gggggg").is_err());
+    }
+
+    #[test]
+    fn every_constructor_hashes_opaque_colors_alike() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::Hasher;
+
+        let hash = |color: Color| {
+            let mut hasher = DefaultHasher::new();
+            color.hash(&mut hasher);
+            hasher.finish()
+        };
+        let expected = hash(Color::rgb(0xff, 0xd8, 0xc2));
+        let deserialized: Color = serde_json::from_str(r#"{"r":255,"g":216,"b":194}"#).unwrap();
+        for color in [
+            Color::from_hex("ffd8c2").unwrap(),
+            Color::from_function("rgb(255, 216, 194)").unwrap(),
+            Color::from_function("rgba(255, 216, 194, 1)").unwrap(),
+            Color::rgba(0xff, 0xd8, 0xc2, 255),
+            deserialized,
+        ] {
+            assert_eq!(color.a, 255, "{:?}", color);
+            assert_eq!(hash(color), expected, "{:?}", color);
+        }
+        assert_ne!(hash(Color::rgba(0xff, 0xd8, 0xc2, 254)), expected);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::color::Color;
+use crate::color::{Background, Color, ColorError};
 use once_cell::sync::OnceCell;
 use serde::de::Error;
 use serde::{Deserialize, Deserializer};
@@ -10,14 +10,34 @@ where
     D: Deserializer<'de>,
 {
     let s: Option<String> = Option::deserialize(deserializer)?;
-    if s.is_none() {
-        return Ok(None);
+    match s.as_deref() {
+        None | Some("") => Ok(None),
+        Some(s) => parse(s).map(Some).map_err(D::Error::custom),
+    }
+}
+
+pub fn background<'de, D>(deserializer: D) -> Result<Option<Background>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Option::deserialize(deserializer)?;
+    match s.as_deref() {
+        None | Some("") => Ok(None),
+        Some("random") => Ok(Some(Background::Random)),
+        Some(s) => parse(s)
+            .map(|color| Some(Background::Color(color)))
+            .map_err(D::Error::custom),
+    }
+}
+
+fn parse(s: &str) -> Result<Color, ColorError> {
+    if s.starts_with("rgb(") || s.starts_with("rgba(") {
+        return Color::from_function(s);
     }
-    let s = s.unwrap();
     let regex = HEX_RE
         .get_or_init(|| regex::Regex::new(r"^(([0-9a-fA-F]{2}){3}|([0-9a-fA-F]){3})$").unwrap());
-    match regex.find(&s) {
-        None => Ok(None),
-        Some(m) => Ok(Some(Color::from_hex(m.as_str()).map_err(D::Error::custom)?)),
+    match regex.find(s) {
+        None => Err(ColorError::Unrecognized(s.to_owned())),
+        Some(m) => Color::from_hex(m.as_str()),
     }
 }
@@ -0,0 +1,153 @@
+use once_cell::sync::OnceCell;
+use thiserror::Error;
+
+pub const MAX_DIMENSION: i32 = 3000;
+pub const DEFAULT_RATIO_WIDTH: u32 = 800;
+// Far longer than any valid size or ratio, but keeps absurd paths away from the regexes.
+pub const MAX_LENGTH: usize = 32;
+
+static DIMENSION_RE: OnceCell<regex::Regex> = OnceCell::new();
+static RATIO_RE: OnceCell<regex::Regex> = OnceCell::new();
+static AXIS_RE: OnceCell<regex::Regex> = OnceCell::new();
+
+#[derive(Debug, Error)]
+pub enum DimensionError {
+    #[error("Invalid dimensions")]
+    Invalid,
+    #[error("dimensions must not be longer than {MAX_LENGTH} characters")]
+    TooLong,
+    #[error("max dimension is {MAX_DIMENSION}x{MAX_DIMENSION}")]
+    TooLarge,
+    #[error("images must not have more than {0} pixels")]
+    TooManyPixels(u64),
+}
+
+pub fn parse(
+    raw: &str,
+    base_width: Option<u32>,
+    default_ratio: (u32, u32),
+) -> Result<(i32, i32), DimensionError> {
+    if raw.len() > MAX_LENGTH {
+        return Err(DimensionError::TooLong);
+    }
+
+    let regex = DIMENSION_RE.get_or_init(|| {
+        regex::Regex::new(r"(?P<length>[1-9][0-9]+)x(?P<height>[1-9][0-9]+)").unwrap()
+    });
+    let (length, height) = match regex.captures(raw) {
+        // The pattern has no upper bound on digits, so anything beyond i32 is too large.
+        Some(caps) => (
+            caps["length"].parse().map_err(|_| DimensionError::TooLarge)?,
+            caps["height"].parse().map_err(|_| DimensionError::TooLarge)?,
+        ),
+        None => match parse_axis(raw, default_ratio) {
+            Some(dimensions) => dimensions?,
+            None => parse_ratio(raw, base_width.unwrap_or(DEFAULT_RATIO_WIDTH))?,
+        },
+    };
+
+    if length > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(DimensionError::TooLarge);
+    }
+
+    Ok((length, height))
+}
+
+// `w800` and `h600` fix one side and take the other one from the default ratio.
+fn parse_axis(
+    raw: &str,
+    (ratio_width, ratio_height): (u32, u32),
+) -> Option<Result<(i32, i32), DimensionError>> {
+    let regex = AXIS_RE
+        .get_or_init(|| regex::Regex::new(r"^(?P<axis>[wh])(?P<size>[1-9][0-9]+)$").unwrap());
+    let caps = regex.captures(raw)?;
+    let size: i32 = match caps["size"].parse() {
+        Ok(size) => size,
+        Err(_) => return Some(Err(DimensionError::TooLarge)),
+    };
+    let (ratio_width, ratio_height) = (ratio_width as f64, ratio_height as f64);
+    let dimensions = match &caps["axis"] {
+        "w" => (size, (size as f64 * ratio_height / ratio_width).round() as i32),
+        _ => ((size as f64 * ratio_width / ratio_height).round() as i32, size),
+    };
+    if dimensions.0 < 1 || dimensions.1 < 1 {
+        return Some(Err(DimensionError::Invalid));
+    }
+    Some(Ok(dimensions))
+}
+
+// The sides of a `W:H` ratio as used by PLATZHALTER_DEFAULT_RATIO.
+pub fn parse_ratio_sides(raw: &str) -> Option<(u32, u32)> {
+    let regex = ratio_regex();
+    let caps = regex.captures(raw)?;
+    Some((caps["width"].parse().ok()?, caps["height"].parse().ok()?))
+}
+
+fn parse_ratio(raw: &str, base_width: u32) -> Result<(i32, i32), DimensionError> {
+    let regex = ratio_regex();
+    let caps = regex.captures(raw).ok_or(DimensionError::Invalid)?;
+    let ratio_width: f64 = caps["width"].parse().unwrap();
+    let ratio_height: f64 = caps["height"].parse().unwrap();
+
+    let length = base_width as f64;
+    let height = (length * ratio_height / ratio_width).round();
+    // Huge ratio sides overflow to infinity and end up as NaN here.
+    if !height.is_finite() || height < 1.0 {
+        return Err(DimensionError::Invalid);
+    }
+
+    // Float to int casts saturate, so anything absurd still trips the max dimension check.
+    Ok((length as i32, height as i32))
+}
+
+fn ratio_regex() -> &'static regex::Regex {
+    RATIO_RE.get_or_init(|| {
+        regex::Regex::new(r"^(?P<width>[1-9][0-9]*):(?P<height>[1-9][0-9]*)$").unwrap()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATIO: (u32, u32) = (4, 3);
+
+    #[test]
+    fn rejects_sides_overflowing_i32() {
+        for raw in ["99999999999x100", "100x99999999999"] {
+            assert!(matches!(parse(raw, None, RATIO), Err(DimensionError::TooLarge)), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn rejects_huge_ratios() {
+        for raw in ["1:99999999999", "99999999999:1"] {
+            assert!(parse(raw, None, RATIO).is_err(), "{}", raw);
+        }
+        let raw = format!("1:{}", "9".repeat(30));
+        assert!(matches!(parse(&raw, None, RATIO), Err(DimensionError::TooLarge)), "{}", raw);
+        let raw = format!("1:{}", "9".repeat(400));
+        assert!(parse(&raw, None, RATIO).is_err(), "{}", raw);
+    }
+
+    #[test]
+    fn rejects_overlong_input() {
+        let raw = format!("800x600{}", "0".repeat(MAX_LENGTH));
+        assert!(matches!(parse(&raw, None, RATIO), Err(DimensionError::TooLong)));
+    }
+
+    #[test]
+    fn derives_the_other_side_from_the_ratio() {
+        assert_eq!(parse("w800", None, RATIO).unwrap(), (800, 600));
+        assert_eq!(parse("h600", None, RATIO).unwrap(), (800, 600));
+        assert_eq!(parse("w400", None, (16, 9)).unwrap(), (400, 225));
+    }
+
+    #[test]
+    fn rejects_bad_axis_sizes() {
+        assert!(matches!(parse("w99999999999", None, RATIO), Err(DimensionError::TooLarge)));
+        assert!(matches!(parse("w4000", None, RATIO), Err(DimensionError::TooLarge)));
+        assert!(matches!(parse("w10", None, (1000, 1)), Err(DimensionError::Invalid)));
+        assert!(parse("x800", None, RATIO).is_err());
+    }
+}
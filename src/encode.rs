@@ -0,0 +1,212 @@
+use cairo::ImageSurface;
+use rgb::FromSlice;
+use thiserror::Error;
+
+pub use platzhalter::format::OutputFormat;
+
+pub const DEFAULT_QUALITY: u8 = 82;
+const METERS_PER_INCH: f64 = 0.0254;
+const COMMENT_KEYWORD: &str = "Comment";
+
+#[derive(Debug, Error)]
+pub enum EncodeError {
+    #[error("surface data is not accessible: {0}")]
+    Surface(#[from] cairo::BorrowError),
+    #[error("failed to encode png: {0}")]
+    Png(#[from] png::EncodingError),
+    #[error("failed to encode jpeg: {0}")]
+    Jpeg(#[from] jpeg_encoder::EncodingError),
+    #[error("failed to encode gif: {0}")]
+    Gif(#[from] gif::EncodingError),
+    #[error("failed to encode avif: {0}")]
+    Avif(#[from] ravif::Error),
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Quality {
+    Lossy(u8),
+    /// Only WebP has a separate lossless mode, every other format ignores it.
+    Lossless,
+}
+
+pub fn encode(
+    mut surface: ImageSurface,
+    format: OutputFormat,
+    quality: Quality,
+    dpi: Option<u32>,
+    comment: Option<&str>,
+) -> Result<Vec<u8>, EncodeError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    let lossy_quality = match quality {
+        Quality::Lossy(quality) => quality,
+        Quality::Lossless => 100,
+    };
+    match format {
+        OutputFormat::Png => encode_png(&mut surface, &mut bytes, dpi, comment)?,
+        OutputFormat::Jpeg => {
+            let rgba = to_rgba(&mut surface)?;
+            jpeg_encoder::Encoder::new(&mut bytes, lossy_quality).encode(
+                &rgba,
+                surface.width() as u16,
+                surface.height() as u16,
+                jpeg_encoder::ColorType::Rgba,
+            )?;
+        }
+        OutputFormat::Webp => {
+            let rgba = to_rgba(&mut surface)?;
+            let encoder = webp::Encoder::from_rgba(
+                &rgba,
+                surface.width() as u32,
+                surface.height() as u32,
+            );
+            let webp = match quality {
+                Quality::Lossy(quality) => encoder.encode(quality as f32),
+                Quality::Lossless => encoder.encode_lossless(),
+            };
+            bytes.extend_from_slice(&webp);
+        }
+        OutputFormat::Gif => {
+            let width = surface.width() as u16;
+            let height = surface.height() as u16;
+            let mut rgba = to_rgba(&mut surface)?;
+            // NeuQuant builds a 256 color palette from the whole image, which keeps
+            // gradients reasonably smooth compared to a fixed web palette.
+            let frame = gif::Frame::from_rgba_speed(width, height, &mut rgba, 10);
+            let mut encoder = gif::Encoder::new(&mut bytes, width, height, &[])?;
+            encoder.write_frame(&frame)?;
+        }
+        OutputFormat::Avif => {
+            let width = surface.width() as usize;
+            let height = surface.height() as usize;
+            let rgba = to_rgba(&mut surface)?;
+            let encoded = ravif::Encoder::new()
+                .with_quality(lossy_quality as f32)
+                .with_speed(8)
+                .encode_rgba(ravif::Img::new(rgba.as_rgba(), width, height))?;
+            bytes = encoded.avif_file;
+        }
+    }
+    Ok(bytes)
+}
+
+// cairo decides on its own which chunks to write, the png crate only writes the ones
+// added here. Without dpi and comment that is nothing besides the image itself.
+fn encode_png(
+    surface: &mut ImageSurface,
+    bytes: &mut Vec<u8>,
+    dpi: Option<u32>,
+    comment: Option<&str>,
+) -> Result<(), EncodeError> {
+    let rgba = to_rgba(surface)?;
+    let mut encoder = png::Encoder::new(bytes, surface.width() as u32, surface.height() as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+
+    // pHYs: pixels per unit on both axes followed by the unit, where 1 means meter.
+    if let Some(dpi) = dpi {
+        let pixels_per_meter = (dpi as f64 / METERS_PER_INCH).round() as u32;
+        let mut phys = Vec::with_capacity(9);
+        phys.extend_from_slice(&pixels_per_meter.to_be_bytes());
+        phys.extend_from_slice(&pixels_per_meter.to_be_bytes());
+        phys.push(1);
+        writer.write_chunk(png::chunk::pHYs, &phys)?;
+    }
+
+    // tEXt: keyword, a null separator and the text. Settings only allows printable ASCII,
+    // which is valid Latin-1 as the chunk requires.
+    if let Some(comment) = comment {
+        let mut text = Vec::with_capacity(COMMENT_KEYWORD.len() + 1 + comment.len());
+        text.extend_from_slice(COMMENT_KEYWORD.as_bytes());
+        text.push(0);
+        text.extend_from_slice(comment.as_bytes());
+        writer.write_chunk(png::chunk::tEXt, &text)?;
+    }
+
+    writer.write_image_data(&rgba)?;
+    writer.finish()?;
+    Ok(())
+}
+
+// cairo stores premultiplied ARGB in native endianness, encoders want straight RGBA.
+fn to_rgba(surface: &mut ImageSurface) -> Result<Vec<u8>, EncodeError> {
+    surface.flush();
+    let width = surface.width() as usize;
+    let height = surface.height() as usize;
+    let stride = surface.stride() as usize;
+    let data = surface.data()?;
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for row in data.chunks(stride).take(height) {
+        for pixel in row[..width * 4].chunks_exact(4) {
+            let argb = u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+            let a = (argb >> 24) as u8;
+            let unpremultiply = |channel: u32| -> u8 {
+                let channel = channel & 0xff;
+                match a {
+                    0 => 0,
+                    255 => channel as u8,
+                    a => ((channel * 255 + a as u32 / 2) / a as u32).min(255) as u8,
+                }
+            };
+            rgba.extend_from_slice(&[
+                unpremultiply(argb >> 16),
+                unpremultiply(argb >> 8),
+                unpremultiply(argb),
+                a,
+            ]);
+        }
+    }
+    Ok(rgba)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    fn png(dpi: Option<u32>, comment: Option<&str>) -> Vec<u8> {
+        let surface = ImageSurface::create(cairo::Format::ARgb32, 4, 4).unwrap();
+        encode(surface, OutputFormat::Png, Quality::Lossless, dpi, comment).unwrap()
+    }
+
+    // Chunks follow the 8 byte signature as length, type, data and a 4 byte CRC.
+    fn chunks(png: &[u8]) -> Vec<(String, &[u8])> {
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(<[u8; 4]>::try_from(&rest[..4]).unwrap()) as usize;
+            let name = String::from_utf8(rest[4..8].to_vec()).unwrap();
+            chunks.push((name, &rest[8..8 + length]));
+            rest = &rest[12 + length..];
+        }
+        chunks
+    }
+
+    fn chunk_names(png: &[u8]) -> Vec<String> {
+        chunks(png).into_iter().map(|(name, _)| name).collect()
+    }
+
+    #[test]
+    fn writes_no_metadata_by_default() {
+        assert_eq!(chunk_names(&png(None, None)), ["IHDR", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn writes_the_comment_as_text_chunk() {
+        let png = png(None, Some("platzhalter"));
+        assert_eq!(chunk_names(&png), ["IHDR", "tEXt", "IDAT", "IEND"]);
+        let (_, text) = chunks(&png).into_iter().find(|(name, _)| name == "tEXt").unwrap();
+        assert_eq!(text, b"Comment\0platzhalter");
+    }
+
+    #[test]
+    fn writes_dpi_as_pixels_per_meter() {
+        let png = png(Some(72), None);
+        assert_eq!(chunk_names(&png), ["IHDR", "pHYs", "IDAT", "IEND"]);
+        let (_, phys) = chunks(&png).into_iter().find(|(name, _)| name == "pHYs").unwrap();
+        // 72 dpi are 2834.6 pixels per meter.
+        assert_eq!(phys, [0, 0, 11, 19, 0, 0, 11, 19, 1]);
+    }
+}
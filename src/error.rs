@@ -0,0 +1,81 @@
+use actix_web::error::BlockingError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use thiserror::Error;
+use tokio::sync::AcquireError;
+
+use crate::dimensions::DimensionError;
+use crate::render::RenderError;
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error(transparent)]
+    Dimensions(#[from] DimensionError),
+    #[error("{0}")]
+    InvalidQuery(String),
+    #[error("{0}")]
+    InvalidParameter(String),
+    #[error("invalid admin token")]
+    Unauthorized,
+    #[error("failed to render image: {0}")]
+    Render(#[from] RenderError),
+    #[error("render task failed: {0}")]
+    Blocking(#[from] BlockingError),
+    #[error("rendering took longer than {0} ms")]
+    Timeout(u128),
+    #[error("render queue is closed: {0}")]
+    Queue(#[from] AcquireError),
+    #[error("cache failure: {0}")]
+    Cache(#[from] sled::Error),
+    #[error("failed to build archive: {0}")]
+    Archive(#[from] zip::result::ZipError),
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::Dimensions(DimensionError::Invalid)
+            | ApiError::Dimensions(DimensionError::TooLong) => "invalid_dimensions",
+            ApiError::Dimensions(DimensionError::TooLarge)
+            | ApiError::Dimensions(DimensionError::TooManyPixels(_)) => "too_large",
+            ApiError::InvalidQuery(_) => "invalid_query",
+            ApiError::InvalidParameter(_) => "invalid_parameter",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::Render(_) | ApiError::Blocking(_) | ApiError::Queue(_) => "render_failed",
+            ApiError::Timeout(_) => "render_timeout",
+            ApiError::Cache(_) => "cache_failed",
+            ApiError::Archive(_) => "archive_failed",
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Dimensions(_)
+            | ApiError::InvalidQuery(_)
+            | ApiError::InvalidParameter(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Timeout(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Render(_)
+            | ApiError::Blocking(_)
+            | ApiError::Queue(_)
+            | ApiError::Cache(_)
+            | ApiError::Archive(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            error: self.code(),
+            message: self.to_string(),
+        })
+    }
+}
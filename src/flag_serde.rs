@@ -0,0 +1,17 @@
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+pub fn flag<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Option::deserialize(deserializer)?;
+    match s.as_deref() {
+        None | Some("") => Ok(None),
+        Some("1") | Some("true") => Ok(Some(true)),
+        Some("0") | Some("false") => Ok(Some(false)),
+        Some(other) => Err(D::Error::custom(format!(
+            "expected one of 1, 0, true or false but got {other}"
+        ))),
+    }
+}
@@ -0,0 +1,64 @@
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::{fs, io};
+
+use tracing::warn;
+
+pub const BUILTIN_FAMILIES: [&str; 3] = ["Sans", "Serif", "Monospace"];
+
+pub fn register_dir(dir: &Path) -> io::Result<Vec<String>> {
+    let mut families: Vec<String> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_font = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| {
+                extension.eq_ignore_ascii_case("ttf") || extension.eq_ignore_ascii_case("otf")
+            })
+            .unwrap_or(false);
+        if !is_font {
+            continue;
+        }
+
+        let family = match family_name(&fs::read(&path)?) {
+            Some(family) => family,
+            None => {
+                warn!("Skipping font {}: no family name found", path.display());
+                continue;
+            }
+        };
+        if !add_font_file(&path) {
+            warn!("Skipping font {}: fontconfig refused it", path.display());
+            continue;
+        }
+        if !families.contains(&family) {
+            families.push(family);
+        }
+    }
+    Ok(families)
+}
+
+fn family_name(data: &[u8]) -> Option<String> {
+    let face = ttf_parser::Face::parse(data, 0).ok()?;
+    face.names()
+        .into_iter()
+        .filter(|name| name.name_id == ttf_parser::name_id::FAMILY)
+        .find_map(|name| name.to_string())
+}
+
+fn add_font_file(path: &Path) -> bool {
+    let path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    // A null config adds the font to the current configuration, which is the one
+    // cairo resolves the families passed to `select_font_face` against.
+    unsafe {
+        fontconfig_sys::FcConfigAppFontAddFile(
+            std::ptr::null_mut(),
+            path.as_ptr() as *const fontconfig_sys::FcChar8,
+        ) != 0
+    }
+}
@@ -0,0 +1,53 @@
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Webp,
+    Gif,
+    Avif,
+}
+
+impl OutputFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Webp => "image/webp",
+            OutputFormat::Gif => "image/gif",
+            OutputFormat::Avif => "image/avif",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Gif => "gif",
+            OutputFormat::Avif => "avif",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "png" => Some(OutputFormat::Png),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "webp" => Some(OutputFormat::Webp),
+            "gif" => Some(OutputFormat::Gif),
+            "avif" => Some(OutputFormat::Avif),
+            _ => None,
+        }
+    }
+
+    pub fn is_lossy(&self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Jpeg | OutputFormat::Webp | OutputFormat::Avif
+        )
+    }
+}
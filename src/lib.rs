@@ -0,0 +1,10 @@
+//! The parts of platzhalter that are useful without running the server. Telemetry lives
+//! here too so integration tests can set it up the same way `main` does.
+
+pub mod color;
+pub mod format;
+
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "server")]
+pub mod telemetry;
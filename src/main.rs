@@ -1,225 +1,920 @@
 use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 
 use actix_cors::Cors;
-use actix_web::{App, error, get, HttpResponse, HttpServer, web::{self, Data}};
+use actix_web::{App, get, HttpMessage, HttpRequest, HttpResponse, HttpServer, web::{self, Data}};
+use actix_web::http::header::{
+    CacheControl, CacheDirective, ContentDisposition, ContentEncoding, DispositionParam,
+    DispositionType, IfModifiedSince, LastModified, CONTENT_TYPE,
+};
+use actix_web::middleware::Compress;
 use actix_web_opentelemetry::{RequestMetricsBuilder, RequestTracing};
-use cairo::{Context, FontSlant, FontWeight, Format, ImageSurface};
-use opentelemetry::{global, KeyValue, sdk::trace as sdktrace};
-use opentelemetry::global::shutdown_tracer_provider;
-use opentelemetry::sdk::export::metrics::aggregation::cumulative_temporality_selector;
-use opentelemetry::sdk::metrics::selectors::simple::inexpensive;
-use opentelemetry::sdk::Resource;
-use opentelemetry_otlp::WithExportConfig;
-use serde::Deserialize;
-use sled::open;
-use tracing;
-use tracing::info;
-use tracing_subscriber::{EnvFilter, Registry};
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::util::SubscriberInitExt;
-
-use crate::color::{Color, PerceivedLuminance};
-
-mod color;
+use bytes::Bytes;
+use opentelemetry::global;
+use ordered_float::OrderedFloat;
+use platzhalter::{color, telemetry};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tracing::{info, info_span, warn, Instrument};
+
+use crate::access_log::RenderOutcome;
+use crate::color::{Background, Color};
+use crate::dimensions::DimensionError;
+use crate::encode::{OutputFormat, Quality};
+use crate::error::ApiError;
+use crate::render::{
+    BorderStyle, Contrast, LabelMode, RenderError, SplitDirection, WatermarkPosition,
+};
+use crate::service::CachedImage;
+use crate::settings::Settings;
+use crate::theme::ColorScheme;
+
+mod access_log;
+mod admin;
+mod batch;
+mod client_ip;
 mod color_serde;
+mod dimensions;
+mod encode;
+mod error;
+mod flag_serde;
+mod fonts;
+mod params;
+mod quality_serde;
+mod render;
+mod rng;
 mod service;
+mod settings;
+mod sprite;
+mod theme;
+mod tile_serde;
 
-static DIMENSION_RE: once_cell::sync::OnceCell<regex::Regex> = once_cell::sync::OnceCell::new();
-
-#[derive(Debug, Hash, Deserialize)]
+#[derive(Debug, Default, Hash, Deserialize)]
 struct ImageConfig {
+    #[serde(default)]
+    #[serde(deserialize_with = "color_serde::background")]
+    bg: Option<Background>,
     #[serde(default)]
     #[serde(deserialize_with = "color_serde::color")]
-    bg: Option<Color>,
+    bg2: Option<Color>,
+    split: Option<SplitDirection>,
     #[serde(default)]
     #[serde(deserialize_with = "color_serde::color")]
     br: Option<Color>,
     br_s: Option<u8>,
+    br_top: Option<u8>,
+    br_right: Option<u8>,
+    br_bottom: Option<u8>,
+    br_left: Option<u8>,
+    #[serde(default)]
+    #[serde(deserialize_with = "flag_serde::flag")]
+    shadow: Option<bool>,
+    fmt: Option<OutputFormat>,
+    #[serde(default)]
+    #[serde(deserialize_with = "quality_serde::quality")]
+    q: Option<Quality>,
+    font: Option<String>,
+    pad: Option<OrderedFloat<f64>>,
+    w: Option<u32>,
+    angle: Option<OrderedFloat<f64>>,
+    #[serde(default)]
+    #[serde(deserialize_with = "flag_serde::flag")]
+    noise: Option<bool>,
+    seed: Option<u64>,
+    label: Option<LabelMode>,
+    #[serde(default)]
+    #[serde(deserialize_with = "color_serde::color")]
+    tc: Option<Color>,
+    #[serde(default)]
+    #[serde(deserialize_with = "color_serde::color")]
+    tc2: Option<Color>,
+    theme: Option<String>,
+    dpi: Option<u32>,
+    br_style: Option<BorderStyle>,
+    contrast: Option<Contrast>,
+    #[serde(default)]
+    #[serde(deserialize_with = "tile_serde::tile")]
+    tile: Option<Vec<u8>>,
+    wm_pos: Option<WatermarkPosition>,
+    #[serde(default)]
+    #[serde(deserialize_with = "color_serde::color")]
+    pagebg: Option<Color>,
+    scheme: Option<ColorScheme>,
+}
+
+impl ImageConfig {
+    // `/800x600.webp` is the same as `/800x600?fmt=webp`. Returns the dimensions without
+    // the extension. Runs first for both requests and specs, so the length limit is
+    // checked on the raw segment before any of it is echoed back in an error.
+    fn apply_extension<'a>(&mut self, dimensions: &'a str) -> Result<&'a str, ApiError> {
+        if dimensions.len() > dimensions::MAX_LENGTH {
+            return Err(DimensionError::TooLong.into());
+        }
+        let (dimensions, extension) = match dimensions.rsplit_once('.') {
+            Some(split) => split,
+            None => return Ok(dimensions),
+        };
+        let format = OutputFormat::from_extension(extension).ok_or_else(|| {
+            ApiError::InvalidParameter(format!("unsupported extension .{extension}"))
+        })?;
+        match self.fmt {
+            Some(fmt) if fmt != format => Err(ApiError::InvalidParameter(format!(
+                "extension .{extension} conflicts with fmt={}",
+                fmt.extension()
+            ))),
+            _ => {
+                self.fmt = Some(format);
+                Ok(dimensions)
+            }
+        }
+    }
+
+    // Explicit parameters win over the theme. The theme name itself is dropped afterwards
+    // so a themed image shares its cache entry with the same colors passed explicitly.
+    fn apply_theme(&mut self) -> Result<(), ApiError> {
+        if let Some(name) = self.theme.take() {
+            let theme = theme::preset(&name)
+                .ok_or_else(|| ApiError::InvalidParameter(format!("unknown theme {name}")))?;
+            self.bg = self.bg.or(theme.bg);
+            self.tc = self.tc.or(theme.tc);
+            self.br = self.br.or(theme.br);
+            self.br_s = self.br_s.or(theme.br_s);
+        }
+
+        // The scheme only replaces the server wide default bg, so it stays in the config
+        // and keeps light and dark variants of an otherwise equal image apart in the cache.
+        if let (None, Some(scheme)) = (self.bg, self.scheme) {
+            let preset = scheme.preset();
+            self.bg = preset.bg;
+            self.tc = self.tc.or(preset.tc);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RequestOptions {
+    #[serde(default)]
+    #[serde(deserialize_with = "flag_serde::flag")]
+    download: Option<bool>,
+    filename: Option<String>,
+    /// Answers with a JSON description of the image instead of the image itself.
+    #[serde(default)]
+    #[serde(deserialize_with = "flag_serde::flag")]
+    meta: Option<bool>,
+    /// Skips the cache lookup and renders the image again. The result still replaces
+    /// the cached entry, so this only bypasses the read and never invalidates anything.
+    #[serde(default)]
+    #[serde(deserialize_with = "flag_serde::flag")]
+    nocache: Option<bool>,
 }
 
 #[derive(Debug, Hash)]
-pub struct ImageMeta<'a> {
+pub struct ImageMeta {
     config: ImageConfig,
-    raw_dimensions: &'a str,
+    length: i32,
+    height: i32,
+}
+
+impl ImageMeta {
+    // The cache key uses the parsed size so e.g. `/16:9` and `/800x450` share an entry.
+    // `w` only feeds the ratio parsing and is already part of that size. Options that
+    // can't change the output are normalized for the same reason.
+    fn new(mut config: ImageConfig, length: i32, height: i32) -> Self {
+        config.w = None;
+        let format = *config.fmt.get_or_insert_default();
+        if !format.is_lossy() {
+            config.q = None;
+        }
+        // Only PNGs carry a pHYs chunk.
+        if format != OutputFormat::Png {
+            config.dpi = None;
+        }
+        // Font families are matched case-insensitively.
+        if let Some(font) = &mut config.font {
+            font.make_ascii_lowercase();
+        }
+        Self {
+            config,
+            length,
+            height,
+        }
+    }
 }
 
 #[get("/{dimensions}")]
 async fn index(
+    req: HttpRequest,
     params: web::Path<String>,
-    web::Query(image_config): web::Query<ImageConfig>,
+    web::Query(mut image_config): web::Query<ImageConfig>,
+    web::Query(options): web::Query<RequestOptions>,
     db: Data<sled::Db>,
-) -> error::Result<HttpResponse> {
+    settings: Data<Settings>,
+    render_permits: Data<Semaphore>,
+) -> Result<HttpResponse, ApiError> {
     let dimensions = params.into_inner();
+    let dimensions = image_config.apply_extension(&dimensions)?;
+    image_config.apply_theme()?;
+    let (length, height) = validate(&image_config, dimensions, &settings)?;
+    let format = image_config.fmt.unwrap_or_default();
+
+    let client_ip = client_ip::client_ip(&req, &settings.trusted_proxies, settings.forwarded_header)
+        .map(|ip| ip.to_string())
+        .unwrap_or_default();
+    let span = info_span!(
+        "placeholder",
+        client_ip = client_ip.as_str(),
+        width = length,
+        height,
+        format = format.extension(),
+        cache_hit = tracing::field::Empty,
+    );
+    let meta = ImageMeta::new(image_config, length, height);
+    let cache_key = service::hash(&meta);
+    let image = cached_render(
+        meta,
+        length,
+        height,
+        options.nocache.unwrap_or(false),
+        &db,
+        &settings,
+        &render_permits,
+    )
+        .instrument(span)
+        .await;
+    let image = match image {
+        Ok(image) => image,
+        Err(err) => return fallback_response(err, &settings),
+    };
+    let outcome = RenderOutcome {
+        cache_hit: image.cached,
+        render_time: image.render_time,
+    };
 
-    let regex = DIMENSION_RE.get_or_init(|| {
-        regex::Regex::new(r"(?P<length>[1-9][0-9]+)x(?P<height>[1-9][0-9]+)").unwrap()
-    });
-    let caps = regex
-        .captures(&dimensions)
-        .ok_or_else(|| error::ErrorBadRequest("Invalid dimensions"))?;
-    let length: i32 = caps["length"].parse().unwrap();
-    let height: i32 = caps["height"].parse().unwrap();
+    // HTTP dates only have second precision, which is all the stored timestamp has too.
+    let not_modified = match req.get_header::<IfModifiedSince>() {
+        Some(IfModifiedSince(since)) => image.created <= SystemTime::from(since),
+        None => false,
+    };
+    let mut response = if options.meta.unwrap_or(false) {
+        HttpResponse::Ok().json(ImageInfo {
+            width: length,
+            height,
+            content_type: format.content_type(),
+            bytes: image.bytes.len(),
+            cache_key: cache_key.to_string(),
+            cached: image.cached,
+        })
+    } else if not_modified {
+        HttpResponse::NotModified()
+            .insert_header(LastModified(image.created.into()))
+            .finish()
+    } else {
+        image_response(image, format, &options, length, height)
+    };
+    response.extensions_mut().insert(outcome);
+
+    Ok(response)
+}
 
-    if length > 3000 || height > 3000 {
-        return Err(error::ErrorBadRequest("max dimension is 3000x3000"));
+// Invalid requests still get their 400, only failures while rendering or reading the cache
+// are covered up. The fallback must never end up in a cache as the real image.
+fn fallback_response(err: ApiError, settings: &Settings) -> Result<HttpResponse, ApiError> {
+    match &settings.fallback_image {
+        Some(fallback) => {
+            warn!("Serving the fallback image: {err}");
+            Ok(HttpResponse::UnprocessableEntity()
+                .content_type(OutputFormat::Png.content_type())
+                .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+                .insert_header(ContentEncoding::Identity)
+                .body(fallback.clone()))
+        }
+        None => Err(err),
     }
+}
 
-    let meta = ImageMeta {
-        config: image_config,
-        raw_dimensions: &dimensions,
-    };
+// Everything that has to hold before an image is worth rendering. Returns the pixel size.
+fn validate(
+    image_config: &ImageConfig,
+    dimensions: &str,
+    settings: &Settings,
+) -> Result<(i32, i32), ApiError> {
+    let (length, height) =
+        dimensions::parse(dimensions, image_config.w, settings.default_ratio)?;
+    if length as u64 * height as u64 > settings.max_pixels {
+        return Err(DimensionError::TooManyPixels(settings.max_pixels).into());
+    }
 
-    if let Some(bytes) =
-        service::get_from_db(db.get_ref(), &meta).map_err(error::ErrorInternalServerError)?
+    let max_border_size = length.min(height) / 2;
+    let border_sizes = [
+        image_config.br_s,
+        image_config.br_top,
+        image_config.br_right,
+        image_config.br_bottom,
+        image_config.br_left,
+    ];
+    if border_sizes
+        .iter()
+        .flatten()
+        .any(|&border_size| border_size as i32 > max_border_size)
     {
-        return Ok(HttpResponse::Ok().content_type("image/png").body(bytes));
-    }
-
-    let surface = ImageSurface::create(Format::ARgb32, length, height)
-        .map_err(error::ErrorBadRequest)?;
-
-    let context = Context::new(&surface).unwrap();
-    let default_color = Color::from_hex("FFD8C2").unwrap();
-    let bg_color = &meta.config.bg.as_ref().unwrap_or(&default_color);
-    let bg_color_scaled = bg_color.to_scaled();
-    context.set_source_rgb(bg_color_scaled.r, bg_color_scaled.g, bg_color_scaled.b);
-    context.paint().unwrap();
-
-    if let Some(border_size) = meta.config.br_s {
-        let br_color = meta
-            .config
-            .br
-            .as_ref()
-            .unwrap_or(&Color::from_hex("000").unwrap())
-            .to_scaled();
-        context.set_source_rgb(br_color.r, br_color.g, br_color.b);
-        context.rectangle(
-            0f64,
-            0f64,
-            surface.width() as f64,
-            surface.height() as f64,
-        );
-        context.set_line_width(border_size as f64);
-        context.stroke().unwrap();
+        return Err(ApiError::InvalidParameter(format!(
+            "border size must not exceed half of the shorter side ({max_border_size})"
+        )));
+    }
+    // An explicit `br_s=0` is fine and simply disables the border.
+    if image_config.br.is_some() && border_sizes.iter().all(Option::is_none) {
+        return Err(ApiError::InvalidParameter(
+            "br requires a border size in br_s, br_top, br_right, br_bottom or br_left".to_owned(),
+        ));
+    }
+
+    if image_config.split.is_some() && image_config.bg2.is_none() {
+        return Err(ApiError::InvalidParameter(
+            "split requires a second color in bg2".to_owned(),
+        ));
+    }
+
+    if let Some(font) = &image_config.font {
+        if !settings.has_font(font) {
+            return Err(ApiError::InvalidParameter(format!("unknown font family {font}")));
+        }
+    }
+
+    let format = image_config.fmt.unwrap_or_default();
+    match image_config.q {
+        Some(Quality::Lossy(quality)) if format.is_lossy() && !(1..=100).contains(&quality) => {
+            return Err(ApiError::InvalidParameter(
+                "quality must be between 1 and 100".to_owned(),
+            ));
+        }
+        Some(Quality::Lossless) if matches!(format, OutputFormat::Jpeg | OutputFormat::Avif) => {
+            return Err(ApiError::InvalidParameter(format!(
+                "lossless quality is not supported for {}",
+                format.extension()
+            )));
+        }
+        _ => {}
+    }
+
+    if let Some(tile) = &image_config.tile {
+        // The tile itself is only decoded once, while rendering.
+        let (width, height) = render::tile_size(tile).map_err(|_| {
+            ApiError::InvalidParameter("tile must be a base64 encoded png".to_owned())
+        })?;
+        if width > render::MAX_TILE_SIZE || height > render::MAX_TILE_SIZE {
+            return Err(ApiError::InvalidParameter(format!(
+                "tile must not be larger than {0}x{0}",
+                render::MAX_TILE_SIZE
+            )));
+        }
+    }
+
+    if image_config.dpi == Some(0) {
+        return Err(ApiError::InvalidParameter(
+            "dpi must be greater than zero".to_owned(),
+        ));
     }
 
-    context.select_font_face("Sans", FontSlant::Normal, FontWeight::Bold);
-    context.set_font_size(surface.width() as f64 / dimensions.len() as f64 * 1.2);
+    Ok((length, height))
+}
+
+#[derive(Debug, Serialize)]
+struct ImageInfo {
+    width: i32,
+    height: i32,
+    content_type: &'static str,
+    bytes: usize,
+    cache_key: String,
+    cached: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    git_hash: &'static str,
+    build_timestamp: &'static str,
+}
+
+#[get("/version")]
+async fn version() -> HttpResponse {
+    HttpResponse::Ok().json(VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("GIT_HASH"),
+        build_timestamp: env!("BUILD_TIMESTAMP"),
+    })
+}
+
+#[get("/preview")]
+async fn preview() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("preview.html"))
+}
+
+#[get("/favicon.ico")]
+async fn favicon(
+    db: Data<sled::Db>,
+    settings: Data<Settings>,
+    render_permits: Data<Semaphore>,
+) -> Result<HttpResponse, ApiError> {
+    let meta = ImageMeta::new(ImageConfig::default(), 32, 32);
+    let image = cached_render(meta, 32, 32, false, &db, &settings, &render_permits).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(OutputFormat::Png.content_type())
+        .insert_header(ContentEncoding::Identity)
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(86400),
+        ]))
+        .body(image.bytes))
+}
+
+async fn cached_render(
+    meta: ImageMeta,
+    length: i32,
+    height: i32,
+    nocache: bool,
+    db: &sled::Db,
+    settings: &Data<Settings>,
+    render_permits: &Data<Semaphore>,
+) -> Result<CachedImage, ApiError> {
+    let meta = Arc::new(meta);
+    let render_meta = Arc::clone(&meta);
+    let render_settings = Data::clone(settings);
+    cached(&*meta, nocache, db, settings, render_permits, move || {
+        render::render(&render_meta, length, height, &render_settings)
+    })
+        .await
+}
 
-    let text_extents = context.text_extents(&dimensions).unwrap();
-    let width = text_extents.width();
-    let height = text_extents.height();
-    let x_bearing = text_extents.x_bearing();
-    let y_bearing = text_extents.y_bearing();
-    let x = surface.width() as f64 / 2.0 - (width / 2.0 + x_bearing);
-    let y = surface.height() as f64 / 2.0 - (height / 2.0 + y_bearing);
-    context.move_to(x, y);
-    let text_color = match bg_color.perceived_luminance() {
-        PerceivedLuminance::Light => Color::from_hex("111827").unwrap(),
-        PerceivedLuminance::Dark => Color::from_hex("F9FAFB").unwrap(),
+// Looks `key` up in the cache and otherwise runs `render` on the blocking pool, storing
+// whatever it produces under that key.
+async fn cached<K, F>(
+    key: &K,
+    nocache: bool,
+    db: &sled::Db,
+    settings: &Settings,
+    render_permits: &Data<Semaphore>,
+    render: F,
+) -> Result<CachedImage, ApiError>
+where
+    K: Hash + ?Sized,
+    F: FnOnce() -> Result<Vec<u8>, RenderError> + Send + 'static,
+{
+    if !nocache {
+        if let Some(image) = service::get_from_db(db, key)? {
+            tracing::Span::current().record("cache_hit", true);
+            return Ok(image);
+        }
     }
-        .to_scaled();
-    context.set_source_rgb(text_color.r, text_color.g, text_color.b);
-    context.show_text(&dimensions).unwrap();
+    tracing::Span::current().record("cache_hit", false);
+
+    let started = Instant::now();
+    let bytes = {
+        let permit = Arc::clone(render_permits).acquire_owned().await?;
+        let timeout = settings.render_timeout;
+        // The blocking task can't be cancelled, so it keeps its permit until it actually
+        // finishes. Nothing is written to the cache when the timeout hits first.
+        let render = web::block(move || {
+            let _permit = permit;
+            render()
+        });
+        tokio::time::timeout(timeout, render)
+            .await
+            .map_err(|_| ApiError::Timeout(timeout.as_millis()))???
+    };
 
-    if surface.width() >= 200 {
-        let border_size: f64 = meta.config.br_s.unwrap_or(0).into();
-        let powered_by_text = "powered by rsmidt.dev";
-        context.select_font_face("Sans", FontSlant::Normal, FontWeight::Normal);
-        let proposed_font_size = surface.width() as f64 / powered_by_text.len() as f64;
-        context.set_font_size(proposed_font_size.clamp(12.0, 40.0));
-        let powered_by_extents = context.text_extents(powered_by_text).unwrap();
-        let x = surface.width() as f64 - powered_by_extents.width() - 5.0 - border_size / 1.5;
-        let y =
-            surface.height() as f64 + powered_by_extents.y_bearing() / 2.0 - border_size / 1.5;
-        context.move_to(x, y);
-        context.set_source_rgba(text_color.r, text_color.g, text_color.b, 0.5);
-        context.show_text(powered_by_text).unwrap();
+    // Cloning an IVec only bumps a reference count, so the cache entry and the response
+    // body share a single copy of the image.
+    let bytes = sled::IVec::from(bytes);
+    let created = service::insert(db, key, bytes.clone())?;
+    if settings.sled_flush_every_ms == 0 {
+        db.flush_async().await?;
     }
 
-    let mut bytes: Vec<u8> = Vec::new();
-    surface.write_to_png(&mut bytes).expect("sdf");
+    Ok(CachedImage {
+        bytes: Bytes::from_owner(bytes),
+        created,
+        cached: false,
+        render_time: Some(started.elapsed()),
+    })
+}
+
+// Specs are `dimensions?query`, exactly the path and query of a regular request.
+fn parse_spec(spec: &str, settings: &Settings) -> Result<ImageMeta, ApiError> {
+    let (dimensions, query) = spec.split_once('?').unwrap_or((spec, ""));
+    let web::Query(mut image_config) = web::Query::<ImageConfig>::from_query(query)
+        .map_err(|err| ApiError::InvalidQuery(err.to_string()))?;
+    let dimensions = image_config.apply_extension(dimensions)?;
+    image_config.apply_theme()?;
+    let (length, height) = validate(&image_config, dimensions, settings)?;
+    Ok(ImageMeta::new(image_config, length, height))
+}
+
+async fn warmup(
+    specs: &[String],
+    db: &sled::Db,
+    settings: &Data<Settings>,
+    render_permits: &Data<Semaphore>,
+) -> usize {
+    let mut warmed = 0;
+    for spec in specs {
+        let result = match parse_spec(spec, settings) {
+            Ok(meta) => {
+                let (length, height) = (meta.length, meta.height);
+                cached_render(meta, length, height, false, db, settings, render_permits).await
+            }
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(_) => warmed += 1,
+            Err(err) => warn!("Skipping warmup of {spec}: {err}"),
+        }
+    }
+    warmed
+}
 
-    service::insert(&db, &meta, bytes.clone()).map_err(error::ErrorInternalServerError)?;
+fn image_response(
+    image: CachedImage,
+    format: OutputFormat,
+    options: &RequestOptions,
+    length: i32,
+    height: i32,
+) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+    // Every output format is already compressed, so keep the compression middleware
+    // from spending time on it. Text responses are still compressed.
+    response.content_type(format.content_type());
+    response.insert_header(ContentEncoding::Identity);
+    response.insert_header(LastModified(image.created.into()));
+    if options.download.unwrap_or(false) || options.filename.is_some() {
+        let filename = options
+            .filename
+            .as_deref()
+            .map(sanitize_filename)
+            .filter(|filename| !filename.is_empty())
+            .map(|filename| {
+                if filename.ends_with(&format!(".{}", format.extension())) {
+                    filename
+                } else {
+                    format!("{filename}.{}", format.extension())
+                }
+            })
+            .unwrap_or_else(|| format!("{length}x{height}.{}", format.extension()));
+        response.insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        });
+    }
+    response.body(image.bytes)
+}
 
-    Ok(HttpResponse::Ok().content_type("image/png").body(bytes))
+fn sanitize_filename(filename: &str) -> String {
+    filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(128)
+        .collect::<String>()
+        .trim_start_matches('.')
+        .to_owned()
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let log_env = std::env::var("RUST_LOG").ok();
-    if log_env.is_none() {
-        std::env::set_var("RUST_LOG", "actix_web=warn,platzhalter=info");
-    }
-
-    let tracer = opentelemetry_otlp::new_pipeline()
-        .tracing()
-        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_env())
-        .with_trace_config(
-            sdktrace::config().with_resource(Resource::new(vec![KeyValue::new(
-                opentelemetry_semantic_conventions::resource::SERVICE_NAME,
-                "platzhalter",
-            )])),
-        )
-        .install_batch(opentelemetry::runtime::Tokio).expect("failed to set up tracer pipeline");
-
-    Registry::default()
-        .with(EnvFilter::try_from_default_env().expect("RUST_LOG not set"))
-        .with(tracing_subscriber::fmt::layer())
-        .with(tracing_opentelemetry::layer().with_tracer(tracer))
-        .init();
+    let mut settings =
+        Settings::from_env().unwrap_or_else(|err| panic!("invalid configuration: {}", err));
+
+    let telemetry = telemetry::init();
+
+    if let Some(font_dir) = &settings.font_dir {
+        settings.fonts = fonts::register_dir(font_dir)?;
+        info!(
+            "Registered font families from {}: {}",
+            font_dir.display(),
+            settings.fonts.join(", ")
+        );
+    }
+    let settings = Data::new(settings);
 
     let meter = global::meter("platzhalter");
     let request_metrics = RequestMetricsBuilder::new().build(meter);
 
-    info!("Starting metrics push");
-    let cx = opentelemetry::Context::new();
-    let metrics_ctrl = opentelemetry_otlp::new_pipeline()
-        .metrics(inexpensive(), cumulative_temporality_selector(), opentelemetry::runtime::Tokio)
-        .with_exporter(
-            opentelemetry_otlp::new_exporter()
-                .tonic()
-                .with_env()
-        )
-        .build()
-        .expect("failed to setup up metrics pipeline");
-
-    metrics_ctrl
-        .start(&cx, opentelemetry::runtime::Tokio)
-        .expect("failed to start otlp metrics push");
-
     let host = std::env::var("PLATZHALTER_HOST")
         .unwrap_or_else(|_| "127.0.0.1:8080".to_owned());
 
-    let db = Data::new(open("platzhalter_db")?);
+    let flush_every_ms = match settings.sled_flush_every_ms {
+        0 => None,
+        ms => Some(ms),
+    };
+    let db = Data::new(
+        sled::Config::new()
+            .path("platzhalter_db")
+            .flush_every_ms(flush_every_ms)
+            .open()?,
+    );
+    let render_permits = Data::new(Semaphore::new(settings.max_concurrent_renders));
+
+    if !settings.warmup.is_empty() {
+        let warmed = warmup(&settings.warmup, &db, &settings, &render_permits).await;
+        info!("Warmed {warmed} of {} cache entries", settings.warmup.len());
+    }
 
     info!("Starting platzhalter running on {host}");
 
-    HttpServer::new(move || {
+    let shutdown_db = db.get_ref().clone();
+
+    // actix stops accepting connections and drains in-flight requests on SIGINT and
+    // SIGTERM by itself, but everything buffered still has to be flushed afterwards.
+    let result = HttpServer::new(move || {
+        // The sprite endpoint takes a JSON body, which makes browsers send a preflight.
         let cors = Cors::default()
-            .allow_any_origin()
-            .allowed_methods(vec!["GET"])
+            .allowed_methods(vec!["GET", "POST"])
+            .allowed_header(CONTENT_TYPE)
             .max_age(3600);
+        let cors = match &settings.cors_origins {
+            None => cors.allow_any_origin(),
+            Some(origins) => origins
+                .iter()
+                .fold(cors.supports_credentials(), |cors, origin| {
+                    cors.allowed_origin(origin)
+                }),
+        };
 
         App::new()
+            .wrap_fn(access_log::middleware)
+            .wrap(Compress::default())
             .wrap(cors)
             .wrap(RequestTracing::new())
             .wrap(request_metrics.clone())
             .app_data(Data::clone(&db))
-            .service(
-                web::resource("/favicon.ico")
-                    .route(web::get().to(|| async { HttpResponse::NotFound().finish() })),
-            )
+            .app_data(Data::clone(&settings))
+            .app_data(Data::clone(&render_permits))
+            .app_data(web::QueryConfig::default().error_handler(|err, _req| {
+                ApiError::InvalidQuery(err.to_string()).into()
+            }))
+            .app_data(web::JsonConfig::default().error_handler(|err, _req| {
+                ApiError::InvalidParameter(err.to_string()).into()
+            }))
+            .service(favicon)
+            .service(version)
+            .service(preview)
+            .service(params::params)
+            .service(admin::purge_cache)
+            .service(sprite::sprite)
+            .service(batch::batch)
             .service(index)
     })
+        .shutdown_timeout(30)
         .bind(&host)?
         .run()
-        .await?;
+        .await;
+
+    info!("Flushing cache");
+    shutdown_db.flush_async().await?;
+
+    // Shutting down blocks until the exporters have flushed, and the exporters run on this
+    // very thread, so it has to wait somewhere else.
+    if let Some(telemetry) = telemetry {
+        web::block(move || telemetry.shutdown())
+            .await
+            .expect("telemetry shutdown panicked");
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::error::QueryPayloadError;
+    use actix_web::http::StatusCode;
+    use actix_web::dev::ServiceResponse;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+
+    use super::*;
+
+    // Spelled out instead of read from the environment so local PLATZHALTER_* variables
+    // can't change what the tests see.
+    fn settings() -> Settings {
+        Settings {
+            default_bg: Color::from_hex("FFD8C2").unwrap(),
+            font_dir: None,
+            fonts: Vec::new(),
+            max_concurrent_renders: 2,
+            admin_token: None,
+            cors_origins: None,
+            sled_flush_every_ms: 500,
+            luma_threshold: color::DEFAULT_LUMA_THRESHOLD,
+            max_pixels: 9_000_000,
+            render_timeout: std::time::Duration::from_secs(10),
+            warmup: Vec::new(),
+            watermark_min_size: 200,
+            trusted_proxies: Vec::new(),
+            forwarded_header: client_ip::ForwardedHeader::default(),
+            fallback_image: None,
+            png_comment: None,
+            default_ratio: (1, 1),
+        }
+    }
+
+    // Runs a request through `index` with a throwaway cache.
+    async fn call(uri: &str, settings: Settings) -> ServiceResponse {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let render_permits = Semaphore::new(settings.max_concurrent_renders);
+        let app = App::new()
+            .app_data(Data::new(db))
+            .app_data(Data::new(settings))
+            .app_data(Data::new(render_permits))
+            .service(index);
+        let app = init_service(app).await;
+        let req = TestRequest::get().uri(uri).to_request();
+        call_service(&app, req).await
+    }
+
+    async fn get(uri: &str) -> StatusCode {
+        call(uri, settings()).await.status()
+    }
+
+    // Every `*_serde` deserializer is exercised through the real query struct.
+    fn config(query: &str) -> Result<ImageConfig, QueryPayloadError> {
+        web::Query::<ImageConfig>::from_query(query).map(web::Query::into_inner)
+    }
 
-    shutdown_tracer_provider();
+    #[test]
+    fn parses_flags() {
+        assert_eq!(config("").unwrap().shadow, None);
+        assert_eq!(config("shadow=").unwrap().shadow, None);
+        assert_eq!(config("shadow=1").unwrap().shadow, Some(true));
+        assert_eq!(config("shadow=true").unwrap().shadow, Some(true));
+        assert_eq!(config("shadow=0").unwrap().shadow, Some(false));
+        assert_eq!(config("shadow=false").unwrap().shadow, Some(false));
+    }
+
+    #[test]
+    fn rejects_other_flag_values() {
+        for query in ["shadow=yes", "shadow=2", "shadow=TRUE"] {
+            assert!(config(query).is_err(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn absent_and_empty_colors_are_none() {
+        for query in ["", "br=&bg="] {
+            let parsed = config(query).unwrap();
+            assert!(parsed.br.is_none() && parsed.bg.is_none(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn parses_valid_colors() {
+        let rgba = |color: &Color| (color.r, color.g, color.b, color.a);
+        let parsed = config("br=f00&bg=00FF7f").unwrap();
+        assert_eq!(parsed.br.as_ref().map(rgba), Some((255, 0, 0, 255)));
+        let bg = match parsed.bg {
+            Some(Background::Color(bg)) => rgba(&bg),
+            other => panic!("unexpected background {:?}", other),
+        };
+        assert_eq!(bg, (0, 255, 127, 255));
+
+        let parsed = config("br=rgba(1,%202,%203,%200.5)&bg=random").unwrap();
+        assert_eq!(parsed.br.as_ref().map(rgba), Some((1, 2, 3, 128)));
+        assert!(matches!(parsed.bg, Some(Background::Random)));
+    }
+
+    #[test]
+    fn rejects_malformed_colors() {
+        for query in ["br=notacolor", "br=12345", "bg=%23fff", "bg=rgb(1,2)"] {
+            assert!(config(query).is_err(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn parses_numeric_and_lossless_quality() {
+        assert_eq!(config("").unwrap().q, None);
+        assert_eq!(config("q=").unwrap().q, None);
+        assert_eq!(config("q=75").unwrap().q, Some(Quality::Lossy(75)));
+        assert_eq!(config("q=lossless").unwrap().q, Some(Quality::Lossless));
+    }
+
+    #[test]
+    fn rejects_other_quality_values() {
+        for query in ["q=high", "q=-1", "q=256", "q=7.5"] {
+            assert!(config(query).is_err(), "{}", query);
+        }
+    }
+
+    #[actix_web::test]
+    async fn accepts_border_up_to_half_the_shorter_side() {
+        assert_eq!(get("/200x100?br_s=50").await, StatusCode::OK);
+    }
 
-    info!("Stopping metrics controller");
-    metrics_ctrl.stop(&cx).expect("failed to stop metrics controller");
+    #[actix_web::test]
+    async fn rejects_border_thicker_than_half_the_shorter_side() {
+        assert_eq!(get("/200x100?br_s=51").await, StatusCode::BAD_REQUEST);
+        assert_eq!(get("/100x200?br_s=51").await, StatusCode::BAD_REQUEST);
+    }
 
-    Ok(())
+    #[actix_web::test]
+    async fn pixel_cap_applies_to_the_computed_size() {
+        let capped = || Settings {
+            max_pixels: 1_000_000,
+            ..settings()
+        };
+        assert_eq!(call("/1000x1000", capped()).await.status(), StatusCode::OK);
+        // 1400x788 only exists after the ratio is resolved against the base width.
+        for uri in ["/1001x1000", "/16:9?w=1400"] {
+            let res = call(uri, capped()).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            let body = read_body(res).await;
+            assert!(String::from_utf8_lossy(&body).contains("\"too_large\""), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn border_color_requires_a_border_size() {
+        let validate = |query: &str| validate(&config(query).unwrap(), "200x100", &settings());
+        let err = validate("br=ff0000").unwrap_err();
+        assert!(matches!(err, ApiError::InvalidParameter(_)));
+        for query in ["br=ff0000&br_s=0", "br=ff0000&br_top=2"] {
+            assert!(validate(query).is_ok(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn rejects_long_segments_before_reading_the_extension() {
+        let long = format!("800x600.{}", "x".repeat(dimensions::MAX_LENGTH));
+        for spec in [long.clone(), format!("{long}?fmt=png")] {
+            let err = parse_spec(&spec, &settings()).unwrap_err();
+            assert!(matches!(err, ApiError::Dimensions(DimensionError::TooLong)), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn fallback_image_is_not_compressed_again() {
+        let settings = Settings {
+            fallback_image: Some(Bytes::from_static(b"png")),
+            ..settings()
+        };
+        let response = fallback_response(ApiError::Timeout(1), &settings).unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let encoding = response.headers().get(actix_web::http::header::CONTENT_ENCODING);
+        assert_eq!(encoding.unwrap(), "identity");
+    }
+
+    fn tile_query(width: u32, height: u32) -> String {
+        use base64::Engine;
+
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&vec![0; (width * height * 4) as usize]).unwrap();
+        writer.finish().unwrap();
+        let tile = base64::engine::general_purpose::STANDARD.encode(png);
+        format!("tile={tile}")
+    }
+
+    #[test]
+    fn tile_size_is_checked_before_decoding() {
+        let validate = |query: &str| validate(&config(query).unwrap(), "400x300", &settings());
+        assert!(validate(&tile_query(16, 256)).is_ok());
+        for query in [tile_query(257, 1), tile_query(1, 257), "tile=aGVsbG8=".to_owned()] {
+            let err = validate(&query).unwrap_err();
+            assert!(matches!(err, ApiError::InvalidParameter(_)), "{}", query);
+        }
+    }
+
+    fn cache_key(dimensions: &str, query: &str) -> u64 {
+        let image_config = config(query).unwrap();
+        let (length, height) = validate(&image_config, dimensions, &settings()).unwrap();
+        service::hash(&ImageMeta::new(image_config, length, height))
+    }
+
+    #[test]
+    fn equivalent_requests_share_a_cache_key() {
+        let equivalent = [
+            [("800x450", ""), ("16:9", "w=800"), ("800x450", "fmt=png")],
+            [("800x450", ""), ("800x450", "q=50"), ("800x450", "q=lossless")],
+            [("800x450", "font=Sans"), ("800x450", "font=sans"), ("800x450", "font=SANS")],
+            [("800x450", "fmt=webp"), ("16:9", "fmt=webp"), ("16:9", "fmt=webp&w=800")],
+            [("800x450", "fmt=webp"), ("800x450", "fmt=webp&dpi=300"), ("16:9", "fmt=webp")],
+        ];
+        for specs in equivalent {
+            for (dimensions, query) in &specs[1..] {
+                let key = cache_key(dimensions, query);
+                assert_eq!(cache_key(specs[0].0, specs[0].1), key, "{}?{}", dimensions, query);
+            }
+        }
+        assert_ne!(cache_key("800x450", ""), cache_key("800x451", ""));
+        assert_ne!(cache_key("800x450", "dpi=72"), cache_key("800x450", "dpi=300"));
+        assert_ne!(cache_key("800x450", "fmt=webp&q=50"), cache_key("800x450", "fmt=webp&q=60"));
+    }
 }
@@ -0,0 +1,138 @@
+use actix_web::{get, HttpResponse};
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::forward_to_deserialize_any;
+use serde::Serialize;
+
+use crate::dimensions;
+use crate::encode::{self, OutputFormat};
+use crate::render::{self, BorderStyle, Contrast, LabelMode, SplitDirection, WatermarkPosition};
+use crate::theme::{self, ColorScheme};
+use crate::{ImageConfig, RequestOptions};
+
+#[derive(Debug, Serialize)]
+struct Param {
+    name: &'static str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    values: Vec<&'static str>,
+    default: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+}
+
+// Names and enum values come from the serde derives so a new field can't be forgotten,
+// only the descriptions below are maintained by hand.
+#[get("/params")]
+pub async fn params() -> HttpResponse {
+    let params: Vec<Param> = names_of::<ImageConfig>()
+        .iter()
+        .chain(names_of::<RequestOptions>())
+        .map(|&name| describe(name))
+        .collect();
+    HttpResponse::Ok().json(params)
+}
+
+fn describe(name: &'static str) -> Param {
+    let (kind, values, default): (_, Vec<&'static str>, Option<String>) = match name {
+        "bg" => ("color", vec!["random"], Some("PLATZHALTER_DEFAULT_BG".to_owned())),
+        "bg2" | "tc" | "tc2" | "pagebg" => ("color", vec![], None),
+        "br" => ("color", vec![], Some("000000".to_owned())),
+        "split" => ("enum", names_of::<SplitDirection>().to_vec(), None),
+        "br_s" | "br_top" | "br_right" | "br_bottom" | "br_left" => {
+            ("integer", vec![], Some("0".to_owned()))
+        }
+        "br_style" => ("enum", names_of::<BorderStyle>().to_vec(), Some("solid".to_owned())),
+        "shadow" | "noise" | "download" | "nocache" | "meta" => {
+            ("flag", vec![], Some("false".to_owned()))
+        }
+        "fmt" => ("enum", names_of::<OutputFormat>().to_vec(), Some("png".to_owned())),
+        "q" => (
+            "integer",
+            vec!["lossless"],
+            Some(encode::DEFAULT_QUALITY.to_string()),
+        ),
+        "font" => ("string", vec![], Some("Sans".to_owned())),
+        "pad" | "angle" => ("number", vec![], Some("0".to_owned())),
+        "w" => (
+            "integer",
+            vec![],
+            Some(dimensions::DEFAULT_RATIO_WIDTH.to_string()),
+        ),
+        "seed" | "dpi" => ("integer", vec![], None),
+        "label" => ("enum", names_of::<LabelMode>().to_vec(), Some("size".to_owned())),
+        "theme" => ("enum", theme::NAMES.to_vec(), None),
+        "contrast" => ("enum", names_of::<Contrast>().to_vec(), None),
+        "scheme" => ("enum", names_of::<ColorScheme>().to_vec(), None),
+        "wm_pos" => (
+            "enum",
+            names_of::<WatermarkPosition>().to_vec(),
+            Some("bottom-right".to_owned()),
+        ),
+        "tile" => ("base64", vec![], None),
+        _ => ("string", vec![], None),
+    };
+    Param {
+        name,
+        kind,
+        values,
+        default,
+        note: note(name),
+    }
+}
+
+// Constraints that the type alone doesn't tell.
+fn note(name: &str) -> Option<String> {
+    let note = match name {
+        "filename" => "characters other than ASCII letters, digits, `-`, `_` and `.` become `_`, \
+            leading dots are dropped and at most 128 characters are kept"
+            .to_owned(),
+        "tile" => format!("a png of at most {0}x{0} pixels", render::MAX_TILE_SIZE),
+        _ => return None,
+    };
+    Some(note)
+}
+
+// Deserializing into this hands over the field names of a struct or the variants of an
+// enum and then bails out, which is all that's needed for introspection.
+struct NameCollector<'a>(&'a mut &'static [&'static str]);
+
+fn names_of<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut names: &'static [&'static str] = &[];
+    let _ = T::deserialize(NameCollector(&mut names));
+    names
+}
+
+impl<'de, 'a> Deserializer<'de> for NameCollector<'a> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("only structs and enums can be introspected"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("introspection only"))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = variants;
+        Err(de::Error::custom("introspection only"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map identifier
+        ignored_any
+    }
+}
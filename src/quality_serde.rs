@@ -0,0 +1,21 @@
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+use crate::encode::Quality;
+
+pub fn quality<'de, D>(deserializer: D) -> Result<Option<Quality>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Option::deserialize(deserializer)?;
+    match s.as_deref() {
+        None | Some("") => Ok(None),
+        Some("lossless") => Ok(Some(Quality::Lossless)),
+        Some(other) => match other.parse() {
+            Ok(quality) => Ok(Some(Quality::Lossy(quality))),
+            Err(_) => Err(D::Error::custom(format!(
+                "expected a number or lossless but got {other}"
+            ))),
+        },
+    }
+}
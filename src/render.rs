@@ -0,0 +1,629 @@
+use std::io::Cursor;
+
+use cairo::{
+    Context, Extend, FontSlant, FontWeight, Format, ImageSurface, LinearGradient, SurfacePattern,
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::color::{Background, Color, PerceivedLuminance, ScaledColor};
+use crate::encode::{self, EncodeError, OutputFormat, Quality};
+use crate::rng::Rng;
+use crate::service;
+use crate::settings::Settings;
+use crate::{ImageConfig, ImageMeta};
+
+const LABEL_BOX_RATIO: f64 = 0.8;
+const NOISE_AMPLITUDE: i32 = 24;
+pub const MAX_TILE_SIZE: u32 = 256;
+
+#[derive(Debug, Error)]
+pub enum RenderError {
+    #[error("failed to draw image: {0}")]
+    Cairo(#[from] cairo::Error),
+    #[error("surface data is not accessible: {0}")]
+    Surface(#[from] cairo::BorrowError),
+    #[error("failed to decode tile: {0}")]
+    Tile(#[from] cairo::IoError),
+    #[error(transparent)]
+    Encode(#[from] EncodeError),
+}
+
+#[derive(Debug, Clone, Copy, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SplitDirection {
+    /// Splits along the diagonal from the top left to the bottom right corner.
+    TopLeft,
+    /// Splits along the diagonal from the top right to the bottom left corner.
+    TopRight,
+}
+
+impl SplitDirection {
+    // The second color always fills the triangle above the diagonal.
+    fn trace_upper_half(&self, context: &Context, width: f64, height: f64) {
+        context.move_to(0.0, 0.0);
+        context.line_to(width, 0.0);
+        match self {
+            SplitDirection::TopLeft => context.line_to(width, height),
+            SplitDirection::TopRight => context.line_to(0.0, height),
+        }
+        context.close_path();
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelMode {
+    /// The plain `WIDTHxHEIGHT` label.
+    #[default]
+    Size,
+    /// Width and height separated by a multiplication sign.
+    Wxh,
+    /// No label at all.
+    None,
+}
+
+#[derive(Debug, Clone, Copy, Default, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BorderStyle {
+    #[default]
+    Solid,
+    /// Fades from the border color at the edge to transparent towards the center.
+    Inset,
+    Dashed,
+}
+
+#[derive(Debug, Clone, Copy, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Contrast {
+    /// Pure black or white text and borders, whichever contrasts with the background.
+    High,
+    /// Swaps the background and text colors.
+    Invert,
+}
+
+#[derive(Debug, Clone, Copy, Default, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatermarkPosition {
+    #[default]
+    BottomRight,
+    BottomLeft,
+    BottomCenter,
+    TopRight,
+}
+
+struct BorderSizes {
+    top: f64,
+    right: f64,
+    bottom: f64,
+    left: f64,
+}
+
+impl BorderSizes {
+    fn from_config(config: &ImageConfig) -> Self {
+        let side = |size: Option<u8>| -> f64 { size.or(config.br_s).unwrap_or(0).into() };
+        Self {
+            top: side(config.br_top),
+            right: side(config.br_right),
+            bottom: side(config.br_bottom),
+            left: side(config.br_left),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.top == 0.0 && self.right == 0.0 && self.bottom == 0.0 && self.left == 0.0
+    }
+
+    // The borders sit inside the rectangle starting at `offset` with the given size.
+    fn draw(
+        &self,
+        context: &Context,
+        style: BorderStyle,
+        color: &ScaledColor,
+        offset: f64,
+        width: f64,
+        height: f64,
+    ) -> Result<(), cairo::Error> {
+        let (left, top) = (offset, offset);
+        let (right, bottom) = (offset + width, offset + height);
+        match style {
+            BorderStyle::Solid => {
+                context.set_source_rgb(color.r, color.g, color.b);
+                context.rectangle(left, top, width, self.top);
+                context.rectangle(right - self.right, top, self.right, height);
+                context.rectangle(left, bottom - self.bottom, width, self.bottom);
+                context.rectangle(left, top, self.left, height);
+                context.fill()?;
+            }
+            BorderStyle::Dashed => {
+                context.save()?;
+                context.set_source_rgb(color.r, color.g, color.b);
+                let dash = self.top.max(self.right).max(self.bottom).max(self.left);
+                context.set_dash(&[dash * 3.0, dash * 2.0], 0.0);
+                // Each side is stroked along the middle of its band.
+                let sides = [
+                    (self.top, (left, top + self.top / 2.0), (right, top + self.top / 2.0)),
+                    (
+                        self.right,
+                        (right - self.right / 2.0, top),
+                        (right - self.right / 2.0, bottom),
+                    ),
+                    (
+                        self.bottom,
+                        (left, bottom - self.bottom / 2.0),
+                        (right, bottom - self.bottom / 2.0),
+                    ),
+                    (self.left, (left + self.left / 2.0, top), (left + self.left / 2.0, bottom)),
+                ];
+                for (size, (x0, y0), (x1, y1)) in sides {
+                    if size > 0.0 {
+                        context.set_line_width(size);
+                        context.move_to(x0, y0);
+                        context.line_to(x1, y1);
+                        context.stroke()?;
+                    }
+                }
+                context.restore()?;
+            }
+            BorderStyle::Inset => {
+                // Gradient start and end point per side, plus the rectangle it fills.
+                let sides = [
+                    (self.top, (left, top, left, top + self.top), (left, top, width, self.top)),
+                    (
+                        self.right,
+                        (right, top, right - self.right, top),
+                        (right - self.right, top, self.right, height),
+                    ),
+                    (
+                        self.bottom,
+                        (left, bottom, left, bottom - self.bottom),
+                        (left, bottom - self.bottom, width, self.bottom),
+                    ),
+                    (
+                        self.left,
+                        (left, top, left + self.left, top),
+                        (left, top, self.left, height),
+                    ),
+                ];
+                for (size, (x0, y0, x1, y1), (x, y, w, h)) in sides {
+                    if size > 0.0 {
+                        let gradient = LinearGradient::new(x0, y0, x1, y1);
+                        gradient.add_color_stop_rgba(0.0, color.r, color.g, color.b, 1.0);
+                        gradient.add_color_stop_rgba(1.0, color.r, color.g, color.b, 0.0);
+                        context.set_source(&gradient)?;
+                        context.rectangle(x, y, w, h);
+                        context.fill()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn render(
+    meta: &ImageMeta,
+    length: i32,
+    height: i32,
+    settings: &Settings,
+) -> Result<Vec<u8>, RenderError> {
+    let surface = draw(meta, length, height, settings)?;
+    let format = meta.config.fmt.unwrap_or_default();
+    let quality = meta
+        .config
+        .q
+        .unwrap_or(Quality::Lossy(encode::DEFAULT_QUALITY));
+    let comment = settings.png_comment.as_deref();
+    Ok(encode::encode(surface, format, quality, meta.config.dpi, comment)?)
+}
+
+// Every cell is drawn exactly like a standalone image and then copied into the sheet.
+pub fn render_sheet(
+    cells: &[(ImageMeta, i32, i32)],
+    width: i32,
+    height: i32,
+    settings: &Settings,
+) -> Result<Vec<u8>, RenderError> {
+    let sheet = ImageSurface::create(Format::ARgb32, width, height)?;
+    let context = Context::new(&sheet)?;
+    for (meta, x, y) in cells {
+        let cell = draw(meta, meta.length, meta.height, settings)?;
+        context.set_source_surface(&cell, *x as f64, *y as f64)?;
+        context.paint()?;
+    }
+    drop(context);
+    let quality = Quality::Lossy(encode::DEFAULT_QUALITY);
+    let comment = settings.png_comment.as_deref();
+    Ok(encode::encode(sheet, OutputFormat::Png, quality, None, comment)?)
+}
+
+fn draw(
+    meta: &ImageMeta,
+    length: i32,
+    height: i32,
+    settings: &Settings,
+) -> Result<ImageSurface, RenderError> {
+    let surface = ImageSurface::create(Format::ARgb32, length, height)?;
+
+    let context = Context::new(&surface)?;
+    let bg_color = match meta.config.bg {
+        Some(Background::Color(color)) => color,
+        Some(Background::Random) => random_color(meta),
+        None => settings.default_bg,
+    };
+    // Inverting paints the background in the color the text would have had and vice versa.
+    let (bg_color, inverted_text_color) = match meta.config.contrast {
+        Some(Contrast::Invert) => (
+            text_color_for(&bg_color, &meta.config, settings.luma_threshold),
+            Some(bg_color),
+        ),
+        _ => (bg_color, None),
+    };
+    let bg_color_scaled = bg_color.to_scaled();
+    context.set_source_rgba(
+        bg_color_scaled.r,
+        bg_color_scaled.g,
+        bg_color_scaled.b,
+        bg_color_scaled.a,
+    );
+    context.paint()?;
+
+    if let Some(tile) = &meta.config.tile {
+        let pattern = SurfacePattern::create(&decode_tile(tile)?);
+        pattern.set_extend(Extend::Repeat);
+        context.set_source(&pattern)?;
+        context.paint()?;
+    }
+
+    let width = surface.width() as f64;
+    let height = surface.height() as f64;
+    let split = meta.config.split.zip(meta.config.bg2.as_ref());
+    if let Some((direction, bg2)) = split {
+        let bg2_scaled = bg2.to_scaled();
+        context.set_source_rgba(bg2_scaled.r, bg2_scaled.g, bg2_scaled.b, bg2_scaled.a);
+        direction.trace_upper_half(&context, width, height);
+        context.fill()?;
+    }
+    let padding = meta
+        .config
+        .pad
+        .map(|pad| pad.into_inner())
+        .unwrap_or(0.0)
+        .max(0.0)
+        .min((width.min(height) / 2.0 - 1.0).max(0.0));
+    let borders = BorderSizes::from_config(&meta.config);
+
+    if !borders.is_empty() {
+        let br_color = match meta.config.contrast {
+            Some(Contrast::High) => {
+                text_color_for(&bg_color, &meta.config, settings.luma_threshold)
+            }
+            _ => meta.config.br.unwrap_or(Color::rgb(0, 0, 0)),
+        }
+            .to_scaled();
+        borders.draw(
+            &context,
+            meta.config.br_style.unwrap_or_default(),
+            &br_color,
+            padding,
+            width - 2.0 * padding,
+            height - 2.0 * padding,
+        )?;
+    }
+
+    let text_color = inverted_text_color.unwrap_or_else(|| match split {
+        Some((_, bg2)) => split_text_color(&bg_color, bg2, &meta.config, settings.luma_threshold),
+        None => text_color_for(&bg_color, &meta.config, settings.luma_threshold),
+    });
+    // The shadow contrasts with the text rather than the background so it also works
+    // for an explicit text color.
+    let shadow_color = match text_color.perceived_luminance(settings.luma_threshold) {
+        PerceivedLuminance::Light => Color::rgb(0, 0, 0),
+        PerceivedLuminance::Dark => Color::rgb(255, 255, 255),
+    }
+        .to_scaled();
+    let text_color = text_color.to_scaled();
+
+    let label = match meta.config.label.unwrap_or_default() {
+        LabelMode::Size => Some(format!("{}x{}", surface.width(), surface.height())),
+        LabelMode::Wxh => Some(format!("{} × {}", surface.width(), surface.height())),
+        LabelMode::None => None,
+    };
+    if let Some(label) = &label {
+        let font = meta.config.font.as_deref().unwrap_or("Sans");
+        context.select_font_face(font, FontSlant::Normal, FontWeight::Bold);
+        let horizontal_inset = padding + borders.left.max(borders.right);
+        let vertical_inset = padding + borders.top.max(borders.bottom);
+        let angle = meta
+            .config
+            .angle
+            .map(|angle| angle.into_inner())
+            .filter(|angle| angle.is_finite())
+            .unwrap_or(0.0)
+            .rem_euclid(360.0)
+            .to_radians();
+        let font_size = fit_font_size(
+            &context,
+            label,
+            angle,
+            ((width - 2.0 * horizontal_inset) * LABEL_BOX_RATIO).max(1.0),
+            ((height - 2.0 * vertical_inset) * LABEL_BOX_RATIO).max(1.0),
+        )?;
+
+        let text_extents = context.text_extents(label)?;
+        let text_width = text_extents.width();
+        let text_height = text_extents.height();
+        let x_bearing = text_extents.x_bearing();
+        let y_bearing = text_extents.y_bearing();
+        // The label is drawn around the image center so rotating it keeps it centered.
+        let x = -(text_width / 2.0 + x_bearing);
+        let y = -(text_height / 2.0 + y_bearing);
+
+        context.save()?;
+        context.translate(width / 2.0, height / 2.0);
+        context.rotate(angle);
+
+        if meta.config.shadow.unwrap_or(false) {
+            let offset = (font_size / 25.0).max(1.0);
+            context.move_to(x + offset, y + offset);
+            context.set_source_rgba(shadow_color.r, shadow_color.g, shadow_color.b, 0.5);
+            context.show_text(label)?;
+        }
+
+        context.move_to(x, y);
+        match &meta.config.tc2 {
+            // Runs top to bottom over the glyphs, which are centered on the origin here.
+            Some(tc2) => {
+                let tc2 = tc2.to_scaled();
+                let gradient =
+                    LinearGradient::new(0.0, -text_height / 2.0, 0.0, text_height / 2.0);
+                gradient.add_color_stop_rgba(
+                    0.0,
+                    text_color.r,
+                    text_color.g,
+                    text_color.b,
+                    text_color.a,
+                );
+                gradient.add_color_stop_rgba(1.0, tc2.r, tc2.g, tc2.b, tc2.a);
+                context.set_source(&gradient)?;
+                context.text_path(label);
+                context.fill()?;
+            }
+            None => {
+                context.set_source_rgba(text_color.r, text_color.g, text_color.b, text_color.a);
+                context.show_text(label)?;
+            }
+        }
+        context.restore()?;
+    }
+
+    let min_size = settings.watermark_min_size as f64;
+    if width * height >= min_size * min_size {
+        let powered_by_text = "powered by rsmidt.dev";
+        context.select_font_face("Sans", FontSlant::Normal, FontWeight::Normal);
+        let proposed_font_size = surface.width() as f64 / powered_by_text.len() as f64;
+        context.set_font_size(proposed_font_size.clamp(12.0, 40.0));
+        let powered_by_extents = context.text_extents(powered_by_text)?;
+        let inner_width = width - 2.0 * padding - borders.left - borders.right;
+        let inner_height = height - 2.0 * padding - borders.top - borders.bottom;
+        // The label box leaves a margin above and below, the watermark has to stay inside.
+        let free_height = if label.is_some() {
+            inner_height * (1.0 - LABEL_BOX_RATIO) / 2.0
+        } else {
+            inner_height
+        };
+        if powered_by_extents.width() + 5.0 <= inner_width
+            && powered_by_extents.height() <= free_height
+        {
+            let left = padding + borders.left + 5.0 - powered_by_extents.x_bearing();
+            let right = width - powered_by_extents.width() - 5.0 - padding - borders.right;
+            let center =
+                (width - powered_by_extents.width()) / 2.0 - powered_by_extents.x_bearing();
+            // y_bearing is negative, half of it keeps the same gap to the top and bottom.
+            let bottom = height + powered_by_extents.y_bearing() / 2.0 - padding - borders.bottom;
+            let top = padding + borders.top - powered_by_extents.y_bearing() * 1.5;
+            let (x, y) = match meta.config.wm_pos.unwrap_or_default() {
+                WatermarkPosition::BottomRight => (right, bottom),
+                WatermarkPosition::BottomLeft => (left, bottom),
+                WatermarkPosition::BottomCenter => (center, bottom),
+                WatermarkPosition::TopRight => (right, top),
+            };
+            context.move_to(x, y);
+            context.set_source_rgba(text_color.r, text_color.g, text_color.b, 0.5);
+            context.show_text(powered_by_text)?;
+        }
+    }
+
+    drop(context);
+    let mut surface = surface;
+    if meta.config.noise.unwrap_or(false) {
+        let seed = meta.config.seed.unwrap_or_else(|| service::hash(meta));
+        apply_noise(&mut surface, seed)?;
+    }
+    Ok(surface)
+}
+
+// A translucent background is judged by what it looks like on the page behind it.
+fn text_color_for(background: &Color, config: &ImageConfig, threshold: f64) -> Color {
+    let visible = match &config.pagebg {
+        Some(page) => background.over(page),
+        None => *background,
+    };
+    let luminance = visible.perceived_luminance(threshold);
+    match (config.contrast, luminance) {
+        (Some(Contrast::High), PerceivedLuminance::Light) => Color::rgb(0, 0, 0),
+        (Some(Contrast::High), PerceivedLuminance::Dark) => Color::rgb(255, 255, 255),
+        _ => config.tc.unwrap_or(match luminance {
+            PerceivedLuminance::Light => Color::rgb(0x11, 0x18, 0x27),
+            PerceivedLuminance::Dark => Color::rgb(0xf9, 0xfa, 0xfb),
+        }),
+    }
+}
+
+// The label is centered, so on a split it always straddles the diagonal. Of the text
+// colors picked for either half, the one that stays more readable on its worse half wins.
+fn split_text_color(bg: &Color, bg2: &Color, config: &ImageConfig, threshold: f64) -> Color {
+    let worse_contrast = |text: &Color| text.contrast_ratio(bg).min(text.contrast_ratio(bg2));
+    let on_bg = text_color_for(bg, config, threshold);
+    let on_bg2 = text_color_for(bg2, config, threshold);
+    if worse_contrast(&on_bg2) > worse_contrast(&on_bg) {
+        on_bg2
+    } else {
+        on_bg
+    }
+}
+
+// Only reads the header, so oversized tiles are rejected before anything is decoded.
+pub fn tile_size(png: &[u8]) -> Result<(u32, u32), png::DecodingError> {
+    let reader = png::Decoder::new(Cursor::new(png)).read_info()?;
+    let info = reader.info();
+    Ok((info.width, info.height))
+}
+
+fn decode_tile(png: &[u8]) -> Result<ImageSurface, cairo::IoError> {
+    ImageSurface::create_from_png(&mut Cursor::new(png))
+}
+
+// Seeded from the cache key so the same URL always ends up with the same color.
+fn random_color(meta: &ImageMeta) -> Color {
+    let mut rng = Rng::new(service::hash(meta));
+    Color::from_hsl(rng.next_f64() * 360.0, 0.6, 0.75)
+}
+
+// Works on the raw buffer since going through cairo per pixel is far too slow for large
+// images. Channels are premultiplied, so they must never exceed the pixel's alpha.
+fn apply_noise(surface: &mut ImageSurface, seed: u64) -> Result<(), cairo::BorrowError> {
+    surface.flush();
+    let mut rng = Rng::new(seed);
+    let mut data = surface.data()?;
+    for pixel in data.chunks_exact_mut(4) {
+        let argb = u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+        let alpha = (argb >> 24) as i32;
+        let delta = (rng.next_u64() % (2 * NOISE_AMPLITUDE as u64 + 1)) as i32 - NOISE_AMPLITUDE;
+        let channel = |shift: u32| -> u32 {
+            (((argb >> shift) & 0xff) as i32 + delta).clamp(0, alpha) as u32
+        };
+        let argb = (argb & 0xff00_0000) | (channel(16) << 16) | (channel(8) << 8) | channel(0);
+        pixel.copy_from_slice(&argb.to_ne_bytes());
+    }
+    Ok(())
+}
+
+fn fit_font_size(
+    context: &Context,
+    text: &str,
+    angle: f64,
+    max_width: f64,
+    max_height: f64,
+) -> Result<f64, cairo::Error> {
+    let (sin, cos) = angle.sin_cos();
+    // Borders and padding can leave a box below one pixel, cairo needs a positive size.
+    let mut font_size = max_height.max(1.0);
+    loop {
+        context.set_font_size(font_size);
+        let extents = context.text_extents(text)?;
+        // Bounding box of the text after rotating it.
+        let width = extents.width() * cos.abs() + extents.height() * sin.abs();
+        let height = extents.width() * sin.abs() + extents.height() * cos.abs();
+        if (width <= max_width && height <= max_height) || font_size <= 1.0 {
+            return Ok(font_size);
+        }
+        // Extents grow roughly linearly with the font size, so jump close to the fitting
+        // size right away and only shrink in small steps to account for hinting.
+        let scale = (max_width / width).min(max_height / height);
+        font_size = (font_size * scale.min(0.95)).max(1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::DEFAULT_LUMA_THRESHOLD;
+
+    use super::*;
+
+    fn context() -> Context {
+        let surface = ImageSurface::create(Format::ARgb32, 1, 1).unwrap();
+        Context::new(&surface).unwrap()
+    }
+
+    fn fits(context: &Context, text: &str, max_width: f64, max_height: f64) -> bool {
+        let extents = context.text_extents(text).unwrap();
+        extents.width() <= max_width && extents.height() <= max_height
+    }
+
+    #[test]
+    fn fits_label_into_extreme_aspect_ratios() {
+        let context = context();
+        for (max_width, max_height) in [(2400.0, 8.0), (8.0, 2400.0), (2400.0, 2400.0)] {
+            fit_font_size(&context, "3000x10", 0.0, max_width, max_height).unwrap();
+            assert!(fits(&context, "3000x10", max_width, max_height));
+        }
+    }
+
+    #[test]
+    fn empty_and_single_char_labels_get_a_finite_size() {
+        let context = context();
+        assert_eq!(fit_font_size(&context, "", 0.0, 200.0, 100.0).unwrap(), 100.0);
+        assert_eq!(fit_font_size(&context, "", 0.7, 0.0, 0.0).unwrap(), 1.0);
+
+        let font_size = fit_font_size(&context, "x", 0.0, 200.0, 100.0).unwrap();
+        assert!(font_size.is_finite() && (1.0..=100.0).contains(&font_size));
+        assert!(fits(&context, "x", 200.0, 100.0));
+    }
+
+    #[test]
+    fn label_wider_than_the_canvas_stops_at_the_minimum_size() {
+        let context = context();
+        let label = "3000x3000 powered by platzhalter";
+        let font_size = fit_font_size(&context, label, 0.0, 4.0, 100.0).unwrap();
+        assert_eq!(font_size, 1.0);
+
+        let font_size = fit_font_size(&context, label, 0.0, 120.0, 100.0).unwrap();
+        assert!(font_size > 1.0 && font_size < 100.0);
+        assert!(fits(&context, label, 120.0, 100.0));
+    }
+
+    #[test]
+    fn border_sides_fall_back_to_br_s() {
+        let config = ImageConfig {
+            br_s: Some(4),
+            br_top: Some(10),
+            ..Default::default()
+        };
+        let borders = BorderSizes::from_config(&config);
+        assert_eq!(
+            (borders.top, borders.right, borders.bottom, borders.left),
+            (10.0, 4.0, 4.0, 4.0)
+        );
+        assert!(!borders.is_empty());
+    }
+
+    #[test]
+    fn zero_border_size_draws_no_border() {
+        assert!(BorderSizes::from_config(&ImageConfig::default()).is_empty());
+        let config = ImageConfig {
+            br_s: Some(0),
+            ..Default::default()
+        };
+        assert!(BorderSizes::from_config(&config).is_empty());
+        let config = ImageConfig {
+            br_s: Some(0),
+            br_left: Some(2),
+            ..Default::default()
+        };
+        assert!(!BorderSizes::from_config(&config).is_empty());
+    }
+
+    #[test]
+    fn split_label_stays_readable_on_both_halves() {
+        let config = ImageConfig::default();
+        let (dark, light) = (Color::rgb(0x60, 0x60, 0x60), Color::rgb(0xff, 0xff, 0xff));
+        let threshold = DEFAULT_LUMA_THRESHOLD;
+        // Light text would win on the dark half alone but vanishes on the white one.
+        assert_eq!(text_color_for(&dark, &config, threshold), Color::rgb(0xf9, 0xfa, 0xfb));
+        for (bg, bg2) in [(dark, light), (light, dark)] {
+            let text_color = split_text_color(&bg, &bg2, &config, threshold);
+            assert_eq!(text_color, Color::rgb(0x11, 0x18, 0x27));
+        }
+    }
+}
@@ -1,18 +1,66 @@
-use crate::ImageMeta;
+use bytes::Bytes;
+use sled::IVec;
 use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
 use std::hash::{Hash, Hasher};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-pub fn get_from_db(db: &sled::Db, meta: &ImageMeta) -> sled::Result<Option<Vec<u8>>> {
-    let mut hash = DefaultHasher::new();
-    meta.hash(&mut hash);
-    let hash = hash.finish();
-    let entry: Option<Vec<u8>> = db.get(hash.to_string())?.map(|e| e.to_vec());
-    Ok(entry)
+// Creation times are kept in their own tree under the same key, as big endian seconds
+// since the epoch.
+const CREATED_TREE: &str = "created";
+
+pub struct CachedImage {
+    pub bytes: Bytes,
+    pub created: SystemTime,
+    /// Whether this came out of the cache rather than from a fresh render.
+    pub cached: bool,
+    /// Time spent waiting for a render slot plus rendering, `None` for cache hits.
+    pub render_time: Option<Duration>,
 }
 
-pub fn insert(db: &sled::Db, meta: &ImageMeta, bytes: Vec<u8>) -> sled::Result<Option<sled::IVec>> {
+pub fn hash<K: Hash + ?Sized>(key: &K) -> u64 {
     let mut hash = DefaultHasher::new();
-    meta.hash(&mut hash);
-    let hash = hash.finish();
-    db.insert(hash.to_string(), bytes)
+    key.hash(&mut hash);
+    hash.finish()
+}
+
+pub fn get_from_db<K: Hash + ?Sized>(db: &sled::Db, key: &K) -> sled::Result<Option<CachedImage>> {
+    let key = hash(key).to_string();
+    let bytes = match db.get(&key)? {
+        // Shares the buffer sled handed out instead of copying it for the response.
+        Some(entry) => Bytes::from_owner(entry),
+        None => return Ok(None),
+    };
+    let created = db
+        .open_tree(CREATED_TREE)?
+        .get(&key)?
+        .and_then(|secs| <[u8; 8]>::try_from(secs.as_ref()).ok())
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(u64::from_be_bytes(secs)))
+        // Entries cached before creation times were stored count as fresh.
+        .unwrap_or_else(SystemTime::now);
+    Ok(Some(CachedImage {
+        bytes,
+        created,
+        cached: true,
+        render_time: None,
+    }))
+}
+
+pub fn insert<K: Hash + ?Sized>(db: &sled::Db, key: &K, bytes: IVec) -> sled::Result<SystemTime> {
+    let key = hash(key).to_string();
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    db.insert(&key, bytes)?;
+    db.open_tree(CREATED_TREE)?
+        .insert(&key, &secs.to_be_bytes()[..])?;
+    Ok(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+pub fn purge(db: &sled::Db) -> sled::Result<usize> {
+    let removed = db.len();
+    db.clear()?;
+    db.open_tree(CREATED_TREE)?.clear()?;
+    Ok(removed)
 }
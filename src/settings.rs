@@ -0,0 +1,225 @@
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use bytes::Bytes;
+use ipnet::IpNet;
+use thiserror::Error;
+
+use crate::client_ip::ForwardedHeader;
+use crate::color::{self, Color, ColorError};
+use crate::dimensions;
+use crate::fonts;
+
+#[derive(Debug, Error)]
+pub enum SettingsError {
+    #[error("PLATZHALTER_DEFAULT_BG must be a hex color: {0}")]
+    InvalidDefaultBg(ColorError),
+    #[error("{0} must be a non-negative integer")]
+    InvalidNumber(&'static str),
+    #[error("{0} must be greater than zero")]
+    Zero(&'static str),
+    #[error("PLATZHALTER_DEFAULT_RATIO must look like 16:9")]
+    InvalidDefaultRatio,
+    #[error("PLATZHALTER_LUMA_THRESHOLD must be between 0 and 100")]
+    InvalidLumaThreshold,
+    #[error("PLATZHALTER_TRUSTED_PROXIES contains `{0}`, which is neither an IP nor a CIDR")]
+    InvalidTrustedProxy(String),
+    #[error("PLATZHALTER_FORWARDED_HEADER must be `x-forwarded-for` or `forwarded`, not `{0}`")]
+    InvalidForwardedHeader(String),
+    #[error("PLATZHALTER_FALLBACK_IMAGE {} can't be read: {1}", .0.display())]
+    UnreadableFallbackImage(PathBuf, io::Error),
+    #[error("PLATZHALTER_FALLBACK_IMAGE {} is not a png", .0.display())]
+    InvalidFallbackImage(PathBuf),
+    #[error("PLATZHALTER_PNG_COMMENT must be printable ASCII of at most {MAX_PNG_COMMENT} bytes")]
+    InvalidPngComment,
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const MAX_PNG_COMMENT: usize = 256;
+
+#[derive(Debug)]
+pub struct Settings {
+    pub default_bg: Color,
+    pub font_dir: Option<PathBuf>,
+    pub fonts: Vec<String>,
+    pub max_concurrent_renders: usize,
+    pub admin_token: Option<String>,
+    pub cors_origins: Option<Vec<String>>,
+    /// Interval of sled's background flush. `0` disables it and flushes after every insert.
+    pub sled_flush_every_ms: u64,
+    pub luma_threshold: f64,
+    /// Width and height of the ratio that fills in the missing side of `wN` and `hN`.
+    pub default_ratio: (u32, u32),
+    pub max_pixels: u64,
+    pub render_timeout: Duration,
+    /// `dimensions?query` specs rendered into the cache before the server starts.
+    pub warmup: Vec<String>,
+    /// The watermark is only drawn on images with at least this size squared in pixels.
+    pub watermark_min_size: u32,
+    /// Peers in these networks may set the client address via `forwarded_header`.
+    pub trusted_proxies: Vec<IpNet>,
+    pub forwarded_header: ForwardedHeader,
+    /// Served by `index` with a 422 instead of an error body when rendering fails.
+    pub fallback_image: Option<Bytes>,
+    /// Written as a `Comment` tEXt chunk into every png. Without it pngs carry no text at all.
+    pub png_comment: Option<String>,
+}
+
+impl Settings {
+    pub fn from_env() -> Result<Self, SettingsError> {
+        let default_bg = match std::env::var("PLATZHALTER_DEFAULT_BG") {
+            Ok(hex) => Color::from_hex(hex.trim_start_matches('#'))
+                .map_err(SettingsError::InvalidDefaultBg)?,
+            Err(_) => Color::rgb(0xff, 0xd8, 0xc2),
+        };
+
+        let font_dir = std::env::var_os("PLATZHALTER_FONT_DIR").map(PathBuf::from);
+
+        let max_concurrent_renders = match parse_env("PLATZHALTER_MAX_CONCURRENT_RENDERS")? {
+            Some(0) => return Err(SettingsError::Zero("PLATZHALTER_MAX_CONCURRENT_RENDERS")),
+            Some(max) => max,
+            None => std::thread::available_parallelism()
+                .map(usize::from)
+                .unwrap_or(1),
+        };
+
+        let admin_token = std::env::var("PLATZHALTER_ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+
+        // Unset or `*` keeps allowing any origin.
+        let cors_origins = std::env::var("PLATZHALTER_CORS_ORIGINS")
+            .ok()
+            .filter(|origins| origins.trim() != "*")
+            .map(|origins| {
+                origins
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|origin| !origin.is_empty())
+                    .map(str::to_owned)
+                    .collect()
+            });
+
+        let sled_flush_every_ms = parse_env("PLATZHALTER_SLED_FLUSH_EVERY")?.unwrap_or(500);
+
+        let luma_threshold = match std::env::var("PLATZHALTER_LUMA_THRESHOLD") {
+            Ok(threshold) => threshold
+                .parse::<f64>()
+                .ok()
+                .filter(|threshold| (0.0..=100.0).contains(threshold))
+                .ok_or(SettingsError::InvalidLumaThreshold)?,
+            Err(_) => color::DEFAULT_LUMA_THRESHOLD,
+        };
+
+        let default_ratio = match std::env::var("PLATZHALTER_DEFAULT_RATIO") {
+            Ok(ratio) => dimensions::parse_ratio_sides(ratio.trim())
+                .ok_or(SettingsError::InvalidDefaultRatio)?,
+            Err(_) => (1, 1),
+        };
+
+        let max_pixels = parse_env("PLATZHALTER_MAX_PIXELS")?.unwrap_or(9_000_000);
+
+        let render_timeout = match parse_env("PLATZHALTER_RENDER_TIMEOUT_MS")? {
+            Some(0) => return Err(SettingsError::Zero("PLATZHALTER_RENDER_TIMEOUT_MS")),
+            Some(ms) => Duration::from_millis(ms),
+            None => Duration::from_secs(10),
+        };
+
+        // Commas inside a query value have to be percent-encoded.
+        let warmup = std::env::var("PLATZHALTER_WARMUP")
+            .map(|specs| {
+                specs
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|spec| !spec.is_empty())
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let watermark_min_size = parse_env("PLATZHALTER_WATERMARK_MIN_SIZE")?.unwrap_or(200);
+
+        let trusted_proxies = std::env::var("PLATZHALTER_TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+            .map(|proxy| {
+                proxy
+                    .parse::<IpNet>()
+                    .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| SettingsError::InvalidTrustedProxy(proxy.to_owned()))
+            })
+            .collect::<Result<_, _>>()?;
+        let forwarded_header = match std::env::var("PLATZHALTER_FORWARDED_HEADER") {
+            Err(_) => ForwardedHeader::default(),
+            Ok(header) if header.eq_ignore_ascii_case("x-forwarded-for") => {
+                ForwardedHeader::XForwardedFor
+            }
+            Ok(header) if header.eq_ignore_ascii_case("forwarded") => ForwardedHeader::Forwarded,
+            Ok(header) => return Err(SettingsError::InvalidForwardedHeader(header)),
+        };
+
+        let fallback_image = std::env::var_os("PLATZHALTER_FALLBACK_IMAGE")
+            .map(PathBuf::from)
+            .map(|path| {
+                let image = std::fs::read(&path)
+                    .map_err(|err| SettingsError::UnreadableFallbackImage(path.clone(), err))?;
+                if !image.starts_with(PNG_SIGNATURE) {
+                    return Err(SettingsError::InvalidFallbackImage(path));
+                }
+                Ok(Bytes::from(image))
+            })
+            .transpose()?;
+
+        let png_comment = std::env::var("PLATZHALTER_PNG_COMMENT")
+            .ok()
+            .filter(|comment| !comment.is_empty())
+            .map(|comment| {
+                let printable = comment.bytes().all(|byte| (b' '..=b'~').contains(&byte));
+                if !printable || comment.len() > MAX_PNG_COMMENT {
+                    return Err(SettingsError::InvalidPngComment);
+                }
+                Ok(comment)
+            })
+            .transpose()?;
+
+        Ok(Self {
+            default_bg,
+            font_dir,
+            fonts: Vec::new(),
+            max_concurrent_renders,
+            admin_token,
+            cors_origins,
+            sled_flush_every_ms,
+            luma_threshold,
+            default_ratio,
+            max_pixels,
+            render_timeout,
+            warmup,
+            watermark_min_size,
+            trusted_proxies,
+            forwarded_header,
+            fallback_image,
+            png_comment,
+        })
+    }
+
+    pub fn has_font(&self, family: &str) -> bool {
+        fonts::BUILTIN_FAMILIES
+            .iter()
+            .copied()
+            .chain(self.fonts.iter().map(String::as_str))
+            .any(|known| known.eq_ignore_ascii_case(family))
+    }
+}
+
+fn parse_env<T: FromStr>(name: &'static str) -> Result<Option<T>, SettingsError> {
+    std::env::var(name)
+        .ok()
+        .map(|value| value.parse().map_err(|_| SettingsError::InvalidNumber(name)))
+        .transpose()
+}
@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use actix_web::{post, web::{self, Data}, HttpResponse};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+use crate::dimensions::DimensionError;
+use crate::error::ApiError;
+use crate::render;
+use crate::settings::Settings;
+use crate::ImageMeta;
+
+const MAX_SPRITES: usize = 64;
+
+#[derive(Debug, Deserialize)]
+pub struct SpriteRequest {
+    sprites: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct SpriteResponse {
+    image: String,
+    sprites: Vec<Sprite>,
+}
+
+#[derive(Debug, Serialize)]
+struct Sprite {
+    spec: String,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+// Takes the same `dimensions?query` specs as PLATZHALTER_WARMUP and answers with the
+// sheet as a base64 png next to the position of every sprite in it.
+#[post("/sprite")]
+pub async fn sprite(
+    request: web::Json<SpriteRequest>,
+    db: Data<sled::Db>,
+    settings: Data<Settings>,
+    render_permits: Data<Semaphore>,
+) -> Result<HttpResponse, ApiError> {
+    let specs = request.into_inner().sprites;
+    if specs.is_empty() || specs.len() > MAX_SPRITES {
+        return Err(ApiError::InvalidParameter(format!(
+            "a sprite sheet needs between 1 and {MAX_SPRITES} sprites"
+        )));
+    }
+    let metas = specs
+        .iter()
+        .map(|spec| crate::parse_spec(spec, &settings))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (cells, width, height) = layout(metas);
+    if width as u64 * height as u64 > settings.max_pixels {
+        return Err(DimensionError::TooManyPixels(settings.max_pixels).into());
+    }
+
+    let sprites = specs
+        .into_iter()
+        .zip(&cells)
+        .map(|(spec, (meta, x, y))| Sprite {
+            spec,
+            x: *x,
+            y: *y,
+            width: meta.length,
+            height: meta.height,
+        })
+        .collect();
+
+    // The cells already contain every parsed config and position, so they make a key
+    // that only matches the exact same list.
+    let cells = Arc::new(cells);
+    let render_cells = Arc::clone(&cells);
+    let render_settings = Data::clone(&settings);
+    let image = crate::cached(&*cells, false, &db, &settings, &render_permits, move || {
+        render::render_sheet(&render_cells, width, height, &render_settings)
+    })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(SpriteResponse {
+        image: base64::engine::general_purpose::STANDARD.encode(&image.bytes),
+        sprites,
+    }))
+}
+
+// Fills a roughly square grid row by row. Each row is as tall as its tallest sprite.
+fn layout(metas: Vec<ImageMeta>) -> (Vec<(ImageMeta, i32, i32)>, i32, i32) {
+    let columns = (metas.len() as f64).sqrt().ceil() as usize;
+    let mut cells = Vec::with_capacity(metas.len());
+    let (mut width, mut height) = (0, 0);
+    let (mut x, mut row_height) = (0, 0);
+    for (index, meta) in metas.into_iter().enumerate() {
+        if index > 0 && index % columns == 0 {
+            height += row_height;
+            x = 0;
+            row_height = 0;
+        }
+        let (cell_width, cell_height) = (meta.length, meta.height);
+        cells.push((meta, x, height));
+        x += cell_width;
+        row_height = row_height.max(cell_height);
+        width = width.max(x);
+    }
+    (cells, width, height + row_height)
+}
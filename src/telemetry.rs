@@ -0,0 +1,92 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use opentelemetry::global::shutdown_tracer_provider;
+use opentelemetry::sdk::export::metrics::aggregation::cumulative_temporality_selector;
+use opentelemetry::sdk::metrics::controllers::BasicController;
+use opentelemetry::sdk::metrics::selectors::simple::inexpensive;
+use opentelemetry::sdk::Resource;
+use opentelemetry::{sdk::trace as sdktrace, Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use tracing::{info, warn};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Registry};
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+pub struct Telemetry {
+    cx: Context,
+    metrics_ctrl: BasicController,
+}
+
+// The providers and the subscriber are process wide, so only the first call installs
+// them. Every later call returns None and keeps using what is already there, which lets
+// the app be built more than once in the same process.
+pub fn init() -> Option<Telemetry> {
+    if INSTALLED.swap(true, Ordering::SeqCst) {
+        return None;
+    }
+
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "actix_web=warn,platzhalter=info");
+    }
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_env())
+        .with_trace_config(
+            sdktrace::config().with_resource(Resource::new(vec![KeyValue::new(
+                opentelemetry_semantic_conventions::resource::SERVICE_NAME,
+                "platzhalter",
+            )])),
+        )
+        .install_batch(opentelemetry::runtime::Tokio).expect("failed to set up tracer pipeline");
+
+    let subscriber = Registry::default()
+        .with(EnvFilter::try_from_default_env().expect("RUST_LOG not set"))
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init();
+    if let Err(err) = subscriber {
+        warn!("Keeping the already installed tracing subscriber: {err}");
+    }
+
+    info!("Starting metrics push");
+    let cx = Context::new();
+    let metrics_ctrl = opentelemetry_otlp::new_pipeline()
+        .metrics(inexpensive(), cumulative_temporality_selector(), opentelemetry::runtime::Tokio)
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_env()
+        )
+        .build()
+        .expect("failed to setup up metrics pipeline");
+
+    metrics_ctrl
+        .start(&cx, opentelemetry::runtime::Tokio)
+        .expect("failed to start otlp metrics push");
+
+    Some(Telemetry { cx, metrics_ctrl })
+}
+
+impl Telemetry {
+    pub fn shutdown(self) {
+        info!("Stopping metrics controller");
+        self.metrics_ctrl.stop(&self.cx).expect("failed to stop metrics controller");
+
+        shutdown_tracer_provider();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn second_init_keeps_the_installed_providers() {
+        let first = init();
+        assert!(first.is_some());
+        assert!(init().is_none());
+    }
+}
@@ -0,0 +1,49 @@
+use serde::Deserialize;
+
+use crate::color::{Background, Color};
+use crate::ImageConfig;
+
+pub const NAMES: &[&str] = &["dark", "light", "blueprint"];
+
+/// Hint for the client's `prefers-color-scheme`, only used when no bg is given.
+#[derive(Debug, Clone, Copy, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+impl ColorScheme {
+    // Each scheme borrows the colors of the theme with the same name.
+    pub fn preset(self) -> ImageConfig {
+        let name = match self {
+            ColorScheme::Light => "light",
+            ColorScheme::Dark => "dark",
+        };
+        preset(name).expect("every scheme has a theme")
+    }
+}
+
+pub fn preset(name: &str) -> Option<ImageConfig> {
+    let theme = match name.to_ascii_lowercase().as_str() {
+        "dark" => ImageConfig {
+            bg: Some(Background::Color(Color::rgb(0x11, 0x18, 0x27))),
+            tc: Some(Color::rgb(0xe5, 0xe7, 0xeb)),
+            ..Default::default()
+        },
+        "light" => ImageConfig {
+            bg: Some(Background::Color(Color::rgb(0xf9, 0xfa, 0xfb))),
+            tc: Some(Color::rgb(0x37, 0x41, 0x51)),
+            ..Default::default()
+        },
+        "blueprint" => ImageConfig {
+            bg: Some(Background::Color(Color::rgb(0x1e, 0x3a, 0x8a))),
+            tc: Some(Color::rgb(0xdb, 0xea, 0xfe)),
+            br: Some(Color::rgb(0xdb, 0xea, 0xfe)),
+            br_s: Some(2),
+            ..Default::default()
+        },
+        _ => return None,
+    };
+    Some(theme)
+}
@@ -0,0 +1,27 @@
+use base64::Engine;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+// Base64 inflates by a third, so this still allows swatches of about 12 KiB.
+const MAX_TILE_LENGTH: usize = 16 * 1024;
+
+pub fn tile<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Option::deserialize(deserializer)?;
+    match s.as_deref() {
+        None | Some("") => Ok(None),
+        Some(encoded) if encoded.len() > MAX_TILE_LENGTH => Err(D::Error::custom(format!(
+            "tile must not be longer than {MAX_TILE_LENGTH} characters"
+        ))),
+        Some(encoded) => {
+            // An unescaped `+` arrives as a space after decoding the query string.
+            let encoded = encoded.replace(' ', "+");
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map(Some)
+                .map_err(|err| D::Error::custom(format!("tile must be base64: {err}")))
+        }
+    }
+}